@@ -0,0 +1,4 @@
+//! The parts of the gesture dataset tool that don't need a window, for use from other crates.
+
+pub mod rasterize;
+pub mod recognizer;
@@ -1,35 +1,544 @@
 use eframe::{egui, epi};
-use image::{ImageFormat, Rgb};
+use image::GenericImageView;
+use make_gesture_dataset::rasterize::{
+	add_noise, chaikin_smooth, composite_background, flip_strokes, normalize_strokes, padded_area, point_segment_distance, rasterize, rotate_strokes, simplify_indices, Normalization,
+	OutputFormat, RasterOptions,
+};
+use make_gesture_dataset::recognizer::{recognize, resample_stroke, Template};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-/// We derive Deserialize/Serialize so we can persist app state on shutdown.
+/// Anything that can go wrong while writing a sample to disk.
+#[derive(Debug)]
+pub enum SaveError {
+	Io(std::io::Error),
+	Image(image::ImageError),
+}
+
+impl fmt::Display for SaveError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SaveError::Io(e) => write!(f, "I/O error: {}", e),
+			SaveError::Image(e) => write!(f, "Image error: {}", e),
+		}
+	}
+}
+
+impl std::error::Error for SaveError {}
+
+impl From<std::io::Error> for SaveError {
+	fn from(e: std::io::Error) -> Self {
+		SaveError::Io(e)
+	}
+}
+
+impl From<image::ImageError> for SaveError {
+	fn from(e: image::ImageError) -> Self {
+		SaveError::Image(e)
+	}
+}
+
+/// A single captured pointer sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StrokePoint {
+	pos: egui::Pos2,
+	// Milliseconds since the first point of the stroke this point belongs to.
+	t: f32,
+	// Pen or touch force from 0 to 1.  Input without pressure, like a mouse, records 1.
+	pressure: f32,
+}
+
+/// Strip the timing information from the strokes, leaving only the geometry.
+fn stroke_positions(lines: &[Vec<StrokePoint>]) -> Vec<Vec<egui::Pos2>> {
+	lines.iter().map(|line| line.iter().map(|p| p.pos).collect()).collect()
+}
+
+/// The pressure of every point, laid out like `stroke_positions`.
+fn stroke_pressures(lines: &[Vec<StrokePoint>]) -> Vec<Vec<f32>> {
+	lines.iter().map(|line| line.iter().map(|p| p.pressure).collect()).collect()
+}
+
+/// Rough size, in points, of the longer side of the preview in the side panel.
+const PREVIEW_SIZE: u32 = 128;
+
+/// Reserved class for "no gesture" examples, hand drawn or generated.
+const NEGATIVE_CLASS: &str = "__negative__";
+
+/// A stroke that a second finger joins within this long is taken to be the start of a multi-touch gesture, and dropped.
+const MULTI_TOUCH_GRACE_SECS: f64 = 0.25;
+
+/// How long the "Saved" confirmation stays up after a save.
+const SAVED_FLASH_SECS: f64 = 2.0;
+
+/// Samples per row on a class's contact sheet.
+const CONTACT_SHEET_COLUMNS: u32 = 20;
+
+/// How many of each class's latest samples new ones are checked against for near-duplicates.
+const RECENT_HASH_COUNT: usize = 16;
+
+/// Keys that pick the class at the same position in the class list.
+const CLASS_HOTKEYS: [egui::Key; 9] = [
+	egui::Key::Num1, egui::Key::Num2, egui::Key::Num3, egui::Key::Num4, egui::Key::Num5, egui::Key::Num6, egui::Key::Num7, egui::Key::Num8, egui::Key::Num9,
+];
+
+/// Extra files written alongside each sample's image.
+#[derive(Clone)]
+#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "persistence", serde(default))]
+pub struct ExportOptions {
+	augment_flip_h: bool,
+	augment_flip_v: bool,
+	// How many randomly rotated copies to save, each within +/- `max_rotation_deg`.
+	rotation_augments: u32,
+	max_rotation_deg: f32,
+	// Ramer-Douglas-Peucker tolerance, in canvas units, for the strokes written to the stroke files.  Zero keeps every point.
+	// The image is always drawn from the full strokes.
+	simplify_epsilon: f32,
+	export_json: bool,
+	// Extra stroke JSON copies with the strokes in a random order, for training order-invariant models.
+	// The raster doesn't depend on stroke order, so these get no image of their own.
+	stroke_order_shuffles: u32,
+	export_time_series: bool,
+	export_resampled: bool,
+	export_svg: bool,
+	resample_count: usize,
+}
+
+impl Default for ExportOptions {
+	fn default() -> Self {
+		Self {
+			augment_flip_h: false,
+			augment_flip_v: false,
+			rotation_augments: 0,
+			max_rotation_deg: 15.0,
+			simplify_epsilon: 0.0,
+			export_json: false,
+			stroke_order_shuffles: 0,
+			export_time_series: false,
+			export_resampled: false,
+			export_svg: false,
+			resample_count: 64,
+		}
+	}
+}
+
+/// A sample written during this session, remembered so a mislabeled one can be found and removed.
+pub struct SavedSample {
+	location: SampleLocation,
+	// Every file written for the sample, starting with the main image.
+	files: Vec<PathBuf>,
+	saved_at: u64,
+	// What the recognizer and near-duplicate check learned from it, so moving it to another class can move these along.
+	template: Option<Template>,
+	hash: Option<u64>,
+}
+
+/// Show a Unix timestamp as a UTC time of day, e.g. "14:03:59".
+fn format_time_of_day(timestamp: u64) -> String {
+	let secs = timestamp % 86400;
+	format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// Hand a file to the desktop's default application for it.
+fn open_with_system(path: &Path) -> std::io::Result<()> {
+	#[cfg(target_os = "windows")]
+	let mut command = {
+		let mut command = std::process::Command::new("cmd");
+		command.args(["/C", "start", ""]);
+		command
+	};
+	#[cfg(target_os = "macos")]
+	let mut command = std::process::Command::new("open");
+	#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+	let mut command = std::process::Command::new("xdg-open");
+	command.arg(path).spawn().map(|_| ())
+}
+
+/// Preferences and dataset bookkeeping restored between runs.  Everything else in the app is rebuilt each session.
+/// We derive Deserialize/Serialize so we can persist these on shutdown.
 #[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
 #[cfg_attr(feature = "persistence", serde(default))] // if we add new fields, give them default values when deserializing old state
-pub struct GestureDatasetApp {
+pub struct Settings {
+	// Every class directory is created under this folder.
+	root_dir: PathBuf,
+
+	// Reconciled with the folders on disk at startup, so a stale list only ever gains classes.
 	gestures: Vec<String>,
 	current_gesture: String,
 
 	width: u32,
 	height: u32,
+	raster_options: RasterOptions,
+	match_canvas_aspect: bool,
+	// Only accept points inside an outlined box with the output's aspect ratio, centered on the canvas.
+	bounded_region: bool,
+	dark_mode: bool,
+	// Countdowns and the save flash redraw at most this many times a second, instead of as fast as the screen allows.
+	// Everything else only redraws on input either way.
+	max_timed_fps: Option<u32>,
+	// Draw in a separate, resizable window instead of the central panel.
+	canvas_detached: bool,
+	// Only affects how strokes look on the canvas, never the saved images.
+	stroke_color: egui::Color32,
+	export_options: ExportOptions,
+	// How sample files are named.  See `parse_filename_pattern` for the supported tokens.
+	filename_pattern: String,
+	// Split each class into numbered subfolders ("000", "001", ...) of this many samples, so huge classes stay quick to list.
+	shard_size: Option<u32>,
+	// Drawings shorter than this, in canvas units, are refused on save.  Set to zero to allow taps.
+	min_stroke_length: f32,
+	// Pointer moves closer than this to the last captured point, in canvas units, are dropped to thin out jittery strokes.
+	min_point_spacing: f32,
+	// Once a drawing has this many strokes, starting another is blocked.
+	max_strokes: Option<u32>,
+	// Warn once the canvas holds more points than this, so a long scribble can't grow without bound.
+	max_points: Option<u32>,
+	// Past `max_points`, thin the strokes by raising their point spacing instead of only warning.
+	auto_thin_points: bool,
+	// Samples whose image hash differs from a recent one of the same class in at most this many of its 64 bits are near-duplicates.
+	duplicate_distance: Option<u32>,
+	// Refuse near-duplicates instead of saving them with a warning.
+	skip_duplicates: bool,
+	// How many random scribbles "Generate negatives" saves at once.
+	negatives_to_generate: u32,
+	// Turn off to keep the drawing on the canvas after saving, so variants can be built up and saved one after another.
+	clear_after_save: bool,
+	// Clear the canvas whenever a different class is picked, so a leftover drawing can't be saved under the wrong one.
+	clear_on_class_switch: bool,
+	// Move on to the next class after each save, so collecting in rotation keeps the classes balanced.
+	round_robin: bool,
+	// Quick-draw mode: save automatically once the pen has been idle this many seconds after drawing.
+	auto_save_idle_secs: Option<f32>,
+
+	eraser_radius: f32,
 
-	#[cfg_attr(feature = "persistence", serde(skip))]
-	drawing: Vec<Vec<egui::Pos2>>,
+	// Faint guide lines on the canvas, dividing it into `grid_cells` cells each way.  Never part of the saved raster.
+	show_grid: bool,
+	grid_cells: u32,
+	// A square in the canvas corner the size of one output pixel, to judge how much detail will survive rasterizing.
+	show_pixel_ruler: bool,
+	// Canvas geometry and the pointer's canvas coordinates printed over the canvas, for chasing misplaced strokes.
+	show_debug_overlay: bool,
+	// The class name drawn huge and faint behind the strokes, for tracing characters and digits.
+	show_text_guide: bool,
+	// Guess the class of each finished drawing with a $1 recognizer, from the samples saved so far.
+	show_recognizer: bool,
 
-	#[cfg_attr(feature = "persistence", serde(skip))]
-	sample_count: u32,
+	// A picture composited behind every saved image and the preview, for models that must cope with cluttered backgrounds.
+	background_path: String,
+	use_background: bool,
+
+	// Ghost the last saved drawing behind the canvas, to help keep samples consistent.
+	onion_skin: bool,
+	// Tint strokes on the canvas from blue when drawn slowly to red when drawn fast.  Saved images are unaffected.
+	velocity_color: bool,
+	// Review mode: draw each stroke in its own color, numbered at its start, to check stroke order and segmentation.
+	stroke_colors: bool,
+	// Arrowheads along each stroke pointing the way it was drawn, to catch samples drawn backwards.
+	show_stroke_direction: bool,
+
+	// The next sample number to write for each class, so switching classes never overwrites another class's files.
+	sample_counts: HashMap<String, u32>,
+	// How "Split dataset" divides each class.  Whatever isn't train or validation is test.  The seed makes it repeatable.
+	split_train: f32,
+	split_val: f32,
+	split_seed: u64,
+	// Shrink or stretch every image to the output size when exporting an ImageFolder tree, for classes saved at other sizes.
+	image_folder_resize: bool,
+	// Classes with fewer samples than this are highlighted.  Zero turns the highlight off.
+	target_per_class: u32,
 }
 
-impl Default for GestureDatasetApp {
+impl Default for Settings {
 	fn default() -> Self {
 		Self {
+			root_dir: PathBuf::from("."),
+
 			gestures: Vec::new(),
 			current_gesture: "".to_owned(),
 
 			width: 32,
 			height: 32,
+			raster_options: Default::default(),
+			match_canvas_aspect: false,
+			bounded_region: false,
+			dark_mode: true,
+			max_timed_fps: None,
+			canvas_detached: false,
+			stroke_color: egui::Color32::WHITE,
+			export_options: Default::default(),
+			filename_pattern: "{n}".to_owned(),
+			shard_size: None,
+			min_stroke_length: 0.01,
+			min_point_spacing: 0.0,
+			max_strokes: None,
+			max_points: Some(50_000),
+			auto_thin_points: false,
+			duplicate_distance: None,
+			skip_duplicates: false,
+			negatives_to_generate: 10,
+			clear_after_save: true,
+			clear_on_class_switch: true,
+			round_robin: false,
+			auto_save_idle_secs: None,
+
+			eraser_radius: 10.0,
+			show_grid: false,
+			grid_cells: 4,
+			show_pixel_ruler: false,
+			show_debug_overlay: false,
+			show_text_guide: false,
+			show_recognizer: false,
+			background_path: String::new(),
+			use_background: false,
+			onion_skin: false,
+			velocity_color: false,
+			stroke_colors: false,
+			show_stroke_direction: false,
+
+			sample_counts: HashMap::new(),
+			split_train: 0.7,
+			split_val: 0.15,
+			split_seed: 0,
+			image_folder_resize: false,
+			target_per_class: 0,
+		}
+	}
+}
+
+/// Settings given on the command line.  These win over anything restored from the last run.
+#[derive(Default)]
+pub struct LaunchArgs {
+	root_dir: Option<PathBuf>,
+	width: Option<u32>,
+	height: Option<u32>,
+	classes: Vec<String>,
+}
+
+const USAGE: &str = "Usage: make_gesture_dataset [--root DIR] [--width N] [--height N] [--classes a,b,c]";
+
+impl LaunchArgs {
+	/// Parse the arguments after the program name.
+	fn parse(args: impl Iterator<Item = String>) -> Result<LaunchArgs, String> {
+		let mut parsed = LaunchArgs::default();
+		let mut args = args.peekable();
+		while let Some(arg) = args.next() {
+			// Accept both "--flag value" and "--flag=value".
+			let (flag, inline_value) = match arg.split_once('=') {
+				Some((flag, value)) => (flag.to_owned(), Some(value.to_owned())),
+				None => (arg, None),
+			};
+			if flag == "--help" || flag == "-h" {
+				return Err(USAGE.to_owned());
+			}
+			let value = match inline_value.or_else(|| args.next()) {
+				Some(value) => value,
+				None => return Err(format!("{} needs a value.\n{}", flag, USAGE)),
+			};
+			let parse_size = |value: &str| value.parse::<u32>().map_err(|_| format!("{} expects a whole number, got '{}'.", flag, value));
+			match flag.as_str() {
+				"--root" => parsed.root_dir = Some(PathBuf::from(value)),
+				"--width" => parsed.width = Some(parse_size(&value)?),
+				"--height" => parsed.height = Some(parse_size(&value)?),
+				"--classes" => {
+					for name in value.split(',').filter(|name| !name.trim().is_empty()) {
+						parsed.classes.push(clean_class_name(name).map_err(|e| format!("Bad class '{}': {}", name, e))?);
+					}
+				}
+				_ => return Err(format!("Unknown argument '{}'.\n{}", flag, USAGE)),
+			}
+		}
+		Ok(parsed)
+	}
+
+	/// Where the given classes will live: the `--root` folder if one was given, otherwise the working directory.
+	fn class_dirs(&self) -> Vec<PathBuf> {
+		let root = self.root_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+		self.classes.iter().map(|name| root.join(name)).collect()
+	}
+
+	fn apply(&self, settings: &mut Settings) {
+		if let Some(root_dir) = &self.root_dir {
+			settings.root_dir = root_dir.clone();
+		}
+		if let Some(width) = self.width {
+			settings.width = width;
+		}
+		if let Some(height) = self.height {
+			settings.height = height;
+		}
+		for name in &self.classes {
+			if !settings.gestures.contains(name) {
+				settings.gestures.push(name.clone());
+			}
+		}
+		if settings.current_gesture.is_empty() || !settings.gestures.contains(&settings.current_gesture) {
+			if let Some(first) = self.classes.first() {
+				settings.current_gesture = first.clone();
+			}
+		}
+	}
+}
+
+pub struct GestureDatasetApp {
+	settings: Settings,
+	launch_args: LaunchArgs,
+
+	// What's typed into the dataset folder field.  Only applied to `root_dir` when "Open" is clicked.
+	root_dir_edit: String,
+
+	drawing: Vec<Vec<StrokePoint>>,
+
+	// When staging, saving queues the drawing here instead of writing it, and "Save All" writes the lot.
+	// Each drawing keeps the class and tags it was staged with, so changing either in between can't mislabel it.
+	staging_mode: bool,
+	staged: Vec<(String, Vec<Vec<StrokePoint>>, Vec<String>)>,
+
+	// Value of `ctx.input().time` when the stroke being drawn was started.
+	stroke_start_time: f64,
+	// When a point was last drawn, if anything has been drawn since the last save.  Drives the auto-save countdown.
+	last_activity_time: Option<f64>,
+	// Fingers on a touch screen, first down first.  egui makes the first one the pointer.
+	active_touches: Vec<egui::TouchId>,
+	// Set once a second finger comes down, and kept until every finger lifts.  Nothing is drawn meanwhile.
+	touch_gesture: bool,
+
+	// While erasing, dragging removes points near the pointer instead of drawing.
+	eraser_mode: bool,
+
+	// How far the canvas view is zoomed in, and the canvas position shown at its top left corner.
+	// Only the view changes.  Strokes are stored and saved in the same canvas units at any zoom.
+	canvas_zoom: f32,
+	canvas_pan: egui::Vec2,
+
+	// The last saved drawing, for the onion skin.
+	previous_sample: Vec<Vec<egui::Pos2>>,
+
+	// An image shown faintly under the strokes for tracing, and its size in pixels.  It's never saved.
+	reference_path_edit: String,
+	reference_texture: Option<(egui::TextureId, egui::Vec2)>,
+
+	// Strokes removed by "Clear Painting", so an undo on an empty canvas can bring them back.  They're kept apart from the
+	// stroke history on purpose: the whole drawing comes back in one undo, as it went in one clear.
+	cleared_strokes: Vec<Vec<StrokePoint>>,
+
+	// Strokes removed by undo, waiting to be redone.  Cleared as soon as a new stroke is completed.
+	redo_stack: Vec<Vec<StrokePoint>>,
+
+	// How many samples each class actually has on disk.  Rebuilt from the directories on startup and rescan.
+	saved_counts: HashMap<String, usize>,
+
+	// Samples saved since the app was started, oldest first.
+	session_log: Vec<SavedSample>,
+	// The session log's length as of the last frame, and the `ctx.input().time` it last grew.  Drives the "Saved" flash.
+	seen_log_len: usize,
+	last_save_time: f64,
+	// Comma-separated tags, like "left-handed, fast", written with every sample saved until they're changed.
+	sample_tags: String,
+	// Recognizer templates: every sample saved this session, plus any loaded from stroke JSON on disk.
+	templates: Vec<Template>,
+	// The recognizer's best guess for the canvas, and the stroke and point counts it was made for.
+	prediction: Option<(String, f32)>,
+	predicted_for: (usize, usize),
+	// Image hashes of the last few samples saved to each class this session, oldest first, for spotting near-duplicates.
+	recent_hashes: HashMap<String, VecDeque<u64>>,
+	// The class picked in the "Move last sample to" box.
+	move_target: String,
+
+	// Live preview of the rasterized drawing, and the pixels behind it so we can tell when it needs rebuilding.
+	preview_texture: Option<egui::TextureId>,
+	preview_image: Option<image::RgbaImage>,
+	// Display scale the preview texture was built for.  Moving to a screen with different scaling needs a rebuild.
+	preview_pixels_per_point: f32,
+
+	// Why the last attempt to add a class was refused, shown under the name field.
+	class_name_error: Option<String>,
+
+	// A class waiting on the user to confirm its deletion.
+	pending_delete: Option<String>,
+	// A class whose sample counter is waiting on the user to confirm resetting it.
+	pending_counter_reset: Option<String>,
+
+	// The dataset stats window, and the numbers it shows.  Only recomputed when asked, since it walks every class folder.
+	show_stats: bool,
+	dataset_stats: Option<DatasetStats>,
+	// The folder, classes and raster size dataset.json was last written for.  Any change rewrites it at the end of the frame.
+	metadata_written: Option<(PathBuf, Vec<String>, (u32, u32))>,
+
+	// The last save failure, shown as a banner at the top of the canvas until dismissed.
+	error_message: Option<String>,
+	// What the last export wrote, shown under the error banner until dismissed.
+	status_message: Option<String>,
+
+	// When the next throttled redraw is already due, so each timer doesn't start its own wakeup.
+	scheduled_repaint: Arc<Mutex<Option<Instant>>>,
+
+	// The dataset folder holding our lock file, so it can be released on exit or when another folder is opened.
+	locked_dir: Option<PathBuf>,
+
+	// The picture at `background_path`, loaded when "Load" is clicked or at startup.
+	background_image: Option<image::RgbImage>,
+}
+
+impl Default for GestureDatasetApp {
+	fn default() -> Self {
+		Self {
+			settings: Default::default(),
+			launch_args: Default::default(),
+			root_dir_edit: ".".to_owned(),
 
 			drawing: Default::default(),
+			staging_mode: false,
+			staged: Vec::new(),
+			stroke_start_time: 0.0,
+			last_activity_time: None,
+			active_touches: Vec::new(),
+			touch_gesture: false,
+			eraser_mode: false,
+			canvas_zoom: 1.0,
+			canvas_pan: egui::Vec2::ZERO,
+			previous_sample: Vec::new(),
+			reference_path_edit: String::new(),
+			reference_texture: None,
+			cleared_strokes: Default::default(),
+			redo_stack: Default::default(),
+
+			saved_counts: HashMap::new(),
+
+			session_log: Vec::new(),
+			seen_log_len: 0,
+			last_save_time: f64::NEG_INFINITY,
+			sample_tags: String::new(),
+			templates: Vec::new(),
+			prediction: None,
+			predicted_for: (0, 0),
+			recent_hashes: HashMap::new(),
+			move_target: String::new(),
 
-			sample_count: 0,
+			preview_texture: None,
+			preview_image: None,
+			preview_pixels_per_point: 0.0,
+
+			class_name_error: None,
+			pending_delete: None,
+			pending_counter_reset: None,
+			show_stats: false,
+			dataset_stats: None,
+			metadata_written: None,
+
+			error_message: None,
+			status_message: None,
+
+			scheduled_repaint: Default::default(),
+
+			locked_dir: None,
+
+			background_image: None,
 		}
 	}
 }
@@ -42,45 +551,291 @@ impl epi::App for GestureDatasetApp {
 	/// Called once before the first frame.
 	fn setup(
 		&mut self,
-		_ctx: &egui::CtxRef,
+		ctx: &egui::CtxRef,
 		_frame: &epi::Frame,
 		_storage: Option<&dyn epi::Storage>,
 	) {
-		// Load previous app state (if any).
+		// Load previous settings (if any).  Older versions stored the whole app under the same key with the same field names.
 		// Note that you must enable the `persistence` feature for this to work.
 		#[cfg(feature = "persistence")]
 		if let Some(storage) = _storage {
-			*self = epi::get_value(storage, epi::APP_KEY).unwrap_or_default()
+			self.settings = epi::get_value(storage, epi::APP_KEY).unwrap_or_default()
+		}
+
+		let settings = &mut self.settings;
+		self.launch_args.apply(settings);
+		ctx.set_visuals(if settings.dark_mode { egui::Visuals::dark() } else { egui::Visuals::light() });
+
+		// Folders and files may have been added, or the persisted state lost, since the last run.
+		self.root_dir_edit = settings.root_dir.to_string_lossy().into_owned();
+		rescan_gestures(&settings.root_dir, &settings.filename_pattern, &mut settings.gestures, &mut settings.sample_counts, &mut self.saved_counts);
+		self.error_message = claim_dataset_dir(&mut self.locked_dir, &settings.root_dir);
+		if settings.use_background && !settings.background_path.is_empty() {
+			match image::open(&settings.background_path) {
+				Ok(img) => self.background_image = Some(img.to_rgb8()),
+				Err(e) => self.error_message = Some(format!("Failed to load background {}: {}", settings.background_path, e)),
+			}
 		}
 	}
 
+	/// Called once the window closes.  Leave the dataset folder free for the next run.
+	fn on_exit(&mut self) {
+		claim_dataset_dir(&mut self.locked_dir, Path::new(""));
+	}
+
 	/// Called by the frame work to save state before shutdown.
 	/// Note that you must enable the `persistence` feature for this to work.
 	#[cfg(feature = "persistence")]
 	fn save(&mut self, storage: &mut dyn epi::Storage) {
-		epi::set_value(storage, epi::APP_KEY, self);
+		epi::set_value(storage, epi::APP_KEY, &self.settings);
 	}
 
 	/// Called each time the UI needs repainting, which may be many times per second.
 	/// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
 	fn update(&mut self, ctx: &egui::CtxRef, frame: &epi::Frame) {
 		let Self {
-			gestures: gestures,
+			settings,
+			launch_args: _,
+			root_dir_edit,
+			drawing,
+			staging_mode,
+			staged,
+			stroke_start_time,
+			last_activity_time,
+			active_touches,
+			touch_gesture,
+			eraser_mode,
+			canvas_zoom,
+			canvas_pan,
+			previous_sample,
+			reference_path_edit,
+			reference_texture,
+			cleared_strokes,
+			redo_stack,
+			saved_counts,
+			session_log,
+			seen_log_len,
+			last_save_time,
+			sample_tags,
+			templates,
+			prediction,
+			predicted_for,
+			recent_hashes,
+			move_target,
+			preview_texture,
+			preview_image,
+			preview_pixels_per_point,
+			class_name_error,
+			pending_delete,
+			pending_counter_reset,
+			show_stats,
+			dataset_stats,
+			metadata_written,
+			error_message,
+			status_message,
+			scheduled_repaint,
+			locked_dir,
+			background_image,
+		} = self;
+		let Settings {
+			root_dir,
+			gestures,
 			current_gesture: label,
 			width,
 			height,
-			drawing: drawing,
-			sample_count,
-		} = self;
+			raster_options,
+			match_canvas_aspect,
+			bounded_region,
+			dark_mode,
+			max_timed_fps,
+			canvas_detached,
+			stroke_color,
+			export_options,
+			filename_pattern,
+			shard_size,
+			min_stroke_length,
+			min_point_spacing,
+			max_strokes,
+			max_points,
+			auto_thin_points,
+			duplicate_distance,
+			skip_duplicates,
+			negatives_to_generate,
+			clear_after_save,
+			clear_on_class_switch,
+			round_robin,
+			auto_save_idle_secs,
+			eraser_radius,
+			show_grid,
+			grid_cells,
+			show_pixel_ruler,
+			show_debug_overlay,
+			show_text_guide,
+			show_recognizer,
+			background_path,
+			use_background,
+			onion_skin,
+			velocity_color,
+			stroke_colors,
+			show_stroke_direction,
+			sample_counts,
+			split_train,
+			split_val,
+			split_seed,
+			image_folder_resize,
+			target_per_class,
+		} = settings;
+		let class_before = label.clone();
+
+		// Keyboard shortcuts.  These are ignored while typing into a text field, e.g. a new gesture name.
+		let shortcuts_enabled = !ctx.wants_keyboard_input();
+		let mut undo_requested = shortcuts_enabled && ctx.input().modifiers.command && ctx.input().key_pressed(egui::Key::Z);
+		let mut redo_requested = shortcuts_enabled && ctx.input().modifiers.command && ctx.input().key_pressed(egui::Key::Y);
+		let mut save_requested = shortcuts_enabled && ctx.input().key_pressed(egui::Key::Enter);
+		// Seconds left before quick-draw mode saves, once a stroke is finished.  Nothing happens while the pen is down.
+		let auto_save_in = match (*auto_save_idle_secs, *last_activity_time) {
+			(Some(secs), Some(last)) if drawing.last().is_some_and(|line| line.is_empty()) && drawing.iter().any(|line| !line.is_empty()) => {
+				Some(secs as f64 - (ctx.input().time - last))
+			}
+			_ => None,
+		};
+		if let Some(remaining) = auto_save_in {
+			if remaining <= 0.0 {
+				save_requested = true;
+			} else if let Some(fps) = *max_timed_fps {
+				request_repaint_after(frame, scheduled_repaint, (1.0 / fps as f64).min(remaining));
+			} else {
+				// egui only redraws on input, and an idle pen makes none.
+				ctx.request_repaint();
+			}
+		}
+		let point_count: usize = drawing.iter().map(|line| line.len()).sum();
+		let over_point_limit = max_points.is_some_and(|max| point_count > max as usize);
+		if over_point_limit && *auto_thin_points {
+			thin_strokes(drawing, max_points.unwrap_or_default() as usize, *min_point_spacing);
+		}
+		let mut clear_requested = shortcuts_enabled && ctx.input().key_pressed(egui::Key::Escape);
+		// Bare keys only, so Ctrl+D or Alt+1 stay free for the OS and window manager.
+		let bare_keys = shortcuts_enabled && ctx.input().modifiers.is_none();
+		let mut duplicate_requested = bare_keys && ctx.input().key_pressed(egui::Key::D);
+		// A second finger is a palm or a two-finger scroll, never part of the drawing.  Drawing pauses until every finger lifts.
+		let was_touch_gesture = *touch_gesture;
+		let mut new_touch_stroke = false;
+		for event in ctx.input().events.iter() {
+			if let egui::Event::Touch { id, phase, .. } = event {
+				match phase {
+					egui::TouchPhase::Start => {
+						// Lifting and touching again within one frame still has to start a new stroke.
+						new_touch_stroke |= active_touches.is_empty();
+						active_touches.push(*id);
+						*touch_gesture |= active_touches.len() > 1;
+					}
+					egui::TouchPhase::End | egui::TouchPhase::Cancel => active_touches.retain(|t| t != id),
+					egui::TouchPhase::Move => {}
+				}
+			}
+		}
+		if active_touches.is_empty() {
+			*touch_gesture = false;
+		}
+		if let Some(line) = drawing.last_mut().filter(|line| !line.is_empty()) {
+			if *touch_gesture && !was_touch_gesture && ctx.input().time - *stroke_start_time < MULTI_TOUCH_GRACE_SECS {
+				// The second finger came down right after the first, so the first was starting the gesture, not a stroke.
+				line.clear();
+			} else if new_touch_stroke {
+				drawing.push(vec![]);
+			}
+		}
+
+		// Tab and Shift+Tab step through the classes, wrapping at either end.
+		if shortcuts_enabled && ctx.input().key_pressed(egui::Key::Tab) && !gestures.is_empty() {
+			let count = gestures.len();
+			let next = match gestures.iter().position(|g| g == label) {
+				Some(idx) if ctx.input().modifiers.shift => (idx + count - 1) % count,
+				Some(idx) => (idx + 1) % count,
+				None => 0,
+			};
+			*label = gestures[next].clone();
+		}
+		// Number keys 1 to 9 jump straight to the first nine classes.
+		if bare_keys {
+			for (key, g) in CLASS_HOTKEYS.iter().zip(gestures.iter()) {
+				if ctx.input().key_pressed(*key) {
+					*label = g.clone();
+				}
+			}
+		}
 
 		egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
 			// The top panel is often a good place for a menu bar:
 			egui::menu::bar(ui, |ui| {
 				ui.menu_button("File", |ui| {
+					if ui.button("Export IDX").clicked() {
+						match export_idx(root_dir, gestures, (*width, *height)) {
+							Ok(count) => *status_message = Some(format!("Exported {} samples to IDX", count)),
+							Err(e) => *error_message = Some(format!("Failed to export IDX: {}", e)),
+						}
+					}
+					if ui.button("Export NumPy (.npy)").clicked() {
+						match export_npy(root_dir, gestures, (*width, *height)) {
+							Ok(count) => *status_message = Some(format!("Exported {} samples to .npy", count)),
+							Err(e) => *error_message = Some(format!("Failed to export .npy: {}", e)),
+						}
+					}
+					if ui.button("Export manifest.csv").clicked() {
+						match export_manifest(root_dir, gestures) {
+							Ok(path) => *status_message = Some(format!("Wrote {}", path.display())),
+							Err(e) => *error_message = Some(format!("Failed to export manifest: {}", e)),
+						}
+					}
+					if ui.button("Save metadata").clicked() {
+						match write_dataset_metadata(root_dir, gestures, (*width, *height)) {
+							Ok(path) => *status_message = Some(format!("Wrote {}", path.display())),
+							Err(e) => *error_message = Some(format!("Failed to write dataset.json: {}", e)),
+						}
+					}
+					if ui.button("Re-render all").on_hover_text("Redraw every sample with stroke JSON, and its flipped copies, at the current size and raster options.  Refused if there are rotated copies, whose angles weren't saved.").clicked() {
+						match rerender_dataset(root_dir, gestures, (*width, *height), raster_options, background_image.as_ref().filter(|_| *use_background)) {
+							Ok(count) => *status_message = Some(format!("Re-rendered {} samples", count)),
+							Err(e) => *error_message = Some(format!("Failed to re-render: {}", e)),
+						}
+					}
+					if ui.add_enabled(!label.is_empty(), egui::Button::new("Make contact sheet")).clicked() {
+						match make_contact_sheet(root_dir, label, CONTACT_SHEET_COLUMNS) {
+							Ok(path) => *status_message = Some(format!("Wrote {}", path.display())),
+							Err(e) => *error_message = Some(format!("Failed to make contact sheet: {}", e)),
+						}
+					}
+					if ui.button("Dataset stats").clicked() {
+						*show_stats = !*show_stats;
+						if *show_stats {
+							*dataset_stats = Some(compute_dataset_stats(root_dir, gestures, filename_pattern));
+						}
+					}
 					if ui.button("Quit").clicked() {
 						frame.quit();
 					}
 				});
+				if ui.button(if *dark_mode { "☀ Light" } else { "🌙 Dark" }).clicked() {
+					*dark_mode = !*dark_mode;
+					ctx.set_visuals(if *dark_mode { egui::Visuals::dark() } else { egui::Visuals::light() });
+					// The canvas follows the theme, so keep the default stroke color visible against it.
+					if *dark_mode && *stroke_color == egui::Color32::BLACK {
+						*stroke_color = egui::Color32::WHITE;
+					} else if !*dark_mode && *stroke_color == egui::Color32::WHITE {
+						*stroke_color = egui::Color32::BLACK;
+					}
+				}
+				let mut limited = max_timed_fps.is_some();
+				ui.checkbox(&mut limited, "Low power")
+					.on_hover_text("Redraw timers like the auto-save countdown at a few frames a second instead of continuously, to save battery.");
+				if limited {
+					let mut fps = max_timed_fps.unwrap_or(4);
+					ui.add(egui::DragValue::new(&mut fps).clamp_range(1..=60).suffix(" fps"));
+					*max_timed_fps = Some(fps);
+				} else {
+					*max_timed_fps = None;
+				}
 			});
 		});
 
@@ -89,40 +844,354 @@ impl epi::App for GestureDatasetApp {
 				// This section handles the UI and creation of data directories for gesture classes.
 				// After a user types in the name of a new gesture, see if it's already on the list.
 				// If it isn't, create the data directory and add it.
+				// Everything below is relative to the dataset folder.  Switching folders switches to its classes.
+				ui.label("Dataset Folder: ");
+				ui.horizontal(|ui| {
+					ui.text_edit_singleline(root_dir_edit);
+					if ui.button("Open").clicked() {
+						let new_root = PathBuf::from(root_dir_edit.trim());
+						match ensure_writable_dir(&new_root) {
+							Ok(()) => {
+								*root_dir = new_root;
+								gestures.clear();
+								sample_counts.clear();
+								saved_counts.clear();
+								rescan_gestures(root_dir, filename_pattern, gestures, sample_counts, saved_counts);
+								*error_message = claim_dataset_dir(locked_dir, root_dir);
+							}
+							Err(e) => {
+								*error_message = Some(format!("Failed to open dataset folder: {}", e));
+							}
+						}
+					}
+				});
+
+				ui.separator();
+
 				ui.label("Add New Gesture Class: ");
 				ui.horizontal(|ui| {
 					ui.text_edit_singleline(label);
 					if ui.button("+").clicked() {
-						label.make_ascii_lowercase();
-						if !gestures.contains(&label) { // This is new!  Add it to our listing and make the directory.
-							let _res = std::fs::create_dir(&label);
-							gestures.push(label.clone());
+						match clean_class_name(label) {
+							Ok(name) => {
+								*label = name;
+								*class_name_error = None;
+								if !gestures.contains(label) { // This is new!  Add it to our listing and make the directory.
+									// A class we can't write to would only fail later, on every save.  Refuse it now instead.
+									if let Err(e) = ensure_writable_dir(&root_dir.join(&label)) {
+										*class_name_error = Some(format!("Can't save to {}: {}", root_dir.join(&label).display(), e));
+									} else {
+										gestures.push(label.clone());
+										// The directory may already be full of samples from an earlier session.
+										sample_counts.insert(label.clone(), next_sample_number(&root_dir.join(&label), filename_pattern));
+										saved_counts.insert(label.clone(), count_samples(&root_dir.join(&label), filename_pattern));
+									}
+								}
+							}
+							Err(e) => {
+								*class_name_error = Some(e);
+							}
 						}
 					}
 				});
+				if let Some(e) = class_name_error {
+					ui.colored_label(egui::Color32::RED, e.as_str());
+				}
+
+				if ui.button("Rescan folders").clicked() {
+					rescan_gestures(root_dir, filename_pattern, gestures, sample_counts, saved_counts);
+				}
 
 				ui.separator();
 
+				// Overall progress counts each class only up to the target, so over-collecting one can't hide another.
+				let target = *target_per_class as usize;
+				if target > 0 && !gestures.is_empty() {
+					let collected: usize = gestures.iter().map(|g| saved_counts.get(g).copied().unwrap_or(0).min(target)).sum();
+					let progress = collected as f32 / (target * gestures.len()) as f32;
+					ui.add(egui::ProgressBar::new(progress).text(format!("Overall: {:.0}%", progress * 100.0)));
+				}
+
 				// For each possible directory, add a radio button.  This determines where we save the result images.
+				// The list order is each class's label index in exports, so it can be rearranged.
 				let mut to_remove: Option<usize> = None;
+				let mut to_swap: Option<(usize, usize)> = None;
+				let class_count = gestures.len();
 				for (idx, g) in gestures.iter().enumerate() {
 					ui.horizontal(|ui|{
-						if ui.radio(g.eq(label), g).clicked() {
+						if ui.add_enabled(idx > 0, egui::Button::new("⬆").small()).clicked() {
+							to_swap = Some((idx - 1, idx));
+						}
+						if ui.add_enabled(idx + 1 < class_count, egui::Button::new("⬇").small()).clicked() {
+							to_swap = Some((idx, idx + 1));
+						}
+						// Show how many drawings each class has, flagging those still short of the target.  Augmented copies
+						// aren't new drawings, so neither the count nor the target includes them.
+						let count = saved_counts.get(g).copied().unwrap_or(0);
+						let done = target > 0 && count >= target;
+						let mut text = egui::RichText::new(if done { format!("✔ {} ({})", g, count) } else { format!("{} ({})", g, count) });
+						if target > 0 && count < target {
+							text = text.color(egui::Color32::from_rgb(255, 160, 0));
+						} else if done {
+							text = text.color(egui::Color32::GRAY);
+						}
+						if idx < CLASS_HOTKEYS.len() {
+							ui.label(egui::RichText::new(format!("{}", idx + 1)).weak()).on_hover_text(format!("Press {} to pick this class", idx + 1));
+						}
+						if ui.radio(g.eq(label), text).on_hover_text("Unique drawings saved.  Augmented copies aren't counted").clicked() {
 							*label = g.clone();
 						}
+						if target > 0 {
+							ui.add(egui::ProgressBar::new((count as f32 / target as f32).min(1.0)).desired_width(60.0));
+						}
+						if ui.button("📂").on_hover_text("Open this class's folder").clicked() {
+							if let Err(e) = open_with_system(&root_dir.join(g)) {
+								*error_message = Some(format!("Failed to open {}: {}", root_dir.join(g).display(), e));
+							}
+						}
 						if ui.button("x").clicked() {
 							to_remove = Some(idx);
 						}
+						if ui.button("🗑").on_hover_text("Delete this class").clicked() {
+							*pending_delete = Some(g.clone());
+						}
 					});
 				}
 				if let Some(remove_idx) = to_remove {
-					gestures.remove(remove_idx);
+					let removed = gestures.remove(remove_idx);
+					if removed == *label {
+						label.clear();
+					}
+				}
+				if let Some((a, b)) = to_swap {
+					gestures.swap(a, b);
+				}
+
+				ui.add(egui::Slider::new(target_per_class, 0..=1000).text("target samples per class"));
+
+				// The counter only says where numbering resumes.  Saving still skips numbers that already have files.
+				if gestures.contains(label) {
+					ui.horizontal(|ui| {
+						let next = sample_counts.get(label.as_str()).copied().unwrap_or_else(|| next_sample_number(&root_dir.join(&*label), filename_pattern));
+						ui.label(format!("Next '{}' sample: {}", label, next));
+						if ui.button("Recount").on_hover_text("Continue after the highest sample number on disk, e.g. after editing files by hand").clicked() {
+							sample_counts.insert(label.clone(), next_sample_number(&root_dir.join(&*label), filename_pattern));
+							saved_counts.insert(label.clone(), count_samples(&root_dir.join(&*label), filename_pattern));
+						}
+						if ui.button("Reset").on_hover_text("Start numbering this class from 0 again, filling any gaps").clicked() {
+							*pending_counter_reset = Some(label.clone());
+						}
+					});
 				}
+				ui.add(egui::Slider::new(min_stroke_length, 0.0..=0.5).text("min drawing length"))
+					.on_hover_text("Refuse to save drawings shorter than this.  Zero allows single taps.");
+				ui.add(egui::Slider::new(min_point_spacing, 0.0..=0.05).text("min point spacing"))
+					.on_hover_text("Skip pointer moves this close to the last point.  Zero keeps every move.");
+				ui.horizontal(|ui| {
+					let mut limited = max_strokes.is_some();
+					ui.checkbox(&mut limited, "Limit strokes");
+					if limited {
+						let mut limit = max_strokes.unwrap_or(2);
+						ui.add(egui::Slider::new(&mut limit, 1..=10));
+						*max_strokes = Some(limit);
+					} else {
+						*max_strokes = None;
+					}
+				});
+				ui.horizontal(|ui| {
+					let mut limited = max_points.is_some();
+					ui.checkbox(&mut limited, "Limit points")
+						.on_hover_text("Warn when the canvas holds more points than this.");
+					if limited {
+						let mut limit = max_points.unwrap_or(50_000);
+						ui.add(egui::DragValue::new(&mut limit).clamp_range(100..=10_000_000).speed(100));
+						ui.checkbox(auto_thin_points, "Auto-thin")
+							.on_hover_text("Drop closely spaced points to stay under the limit, instead of only warning.");
+						*max_points = Some(limit);
+					} else {
+						*max_points = None;
+					}
+				});
+				ui.horizontal(|ui| {
+					let mut checked = duplicate_distance.is_some();
+					ui.checkbox(&mut checked, "Catch near-duplicates")
+						.on_hover_text("Compare each sample's image to the last few saved to its class this session.");
+					if checked {
+						let mut distance = duplicate_distance.unwrap_or(4);
+						ui.add(egui::Slider::new(&mut distance, 0..=16).text("bits"));
+						ui.checkbox(skip_duplicates, "Skip");
+						*duplicate_distance = Some(distance);
+					} else {
+						*duplicate_distance = None;
+					}
+				});
 
 				ui.separator();
 
 				ui.add(egui::Slider::new(width, 0..=256).text("width"));
 				ui.add(egui::Slider::new(height, 0..=256).text("height"));
+				ui.checkbox(match_canvas_aspect, "Match canvas to output aspect");
+				ui.checkbox(bounded_region, "Only draw inside output-shaped box");
+				ui.add(egui::Slider::new(&mut raster_options.line_thickness, 1..=8).text("line thickness"));
+				ui.add(egui::Slider::new(&mut raster_options.padding, 0..=32).text("padding"));
+				ui.add(egui::Slider::new(&mut raster_options.smoothing_iterations, 0..=5).text("smoothing"));
+				ui.checkbox(&mut raster_options.antialias, "Anti-alias");
+				ui.add(egui::Slider::new(&mut raster_options.supersample_factor, 1..=8).text("supersampling"));
+				ui.add(egui::Slider::new(&mut raster_options.stamps_per_pixel, 1..=4).text("stamps per pixel"))
+					.on_hover_text("How densely strokes are filled in.  Raise it if anti-aliased strokes look beaded.");
+				ui.checkbox(&mut raster_options.rounded_joins, "Rounded joins");
+				ui.checkbox(&mut raster_options.pressure_thickness, "Pressure controls thickness")
+					.on_hover_text("Only pen and touch input report pressure.  Mouse strokes keep the full thickness.");
+				ui.checkbox(&mut raster_options.preserve_aspect, "Preserve aspect ratio");
+				ui.horizontal(|ui| {
+					ui.checkbox(&mut raster_options.fill_closed, "Fill closed strokes");
+					if raster_options.fill_closed {
+						ui.add(egui::Slider::new(&mut raster_options.close_distance, 0.0..=16.0).text("max gap (px)"))
+							.on_hover_text("A stroke counts as closed if it ends within this many output pixels of its start.");
+					}
+				});
+				ui.add(egui::Slider::new(&mut raster_options.gamma, 0.2..=5.0).logarithmic(true).text("gamma"))
+					.on_hover_text("Shape the anti-aliased edges: coverage is raised to this power.  One leaves them linear.");
+				ui.horizontal(|ui| {
+					ui.add(egui::Slider::new(&mut raster_options.noise_level, 0.0..=0.5).text("noise"))
+						.on_hover_text("Noise mixed into each saved image.  The preview stays clean.");
+					ui.checkbox(&mut raster_options.salt_and_pepper, "Salt and pepper");
+				});
+				egui::ComboBox::from_label("Normalization")
+					.selected_text(match raster_options.normalization {
+						Normalization::BoundingBox => "Bounding box",
+						Normalization::Centroid => "Centroid",
+						Normalization::FixedScale => "Preserve scale",
+					})
+					.show_ui(ui, |ui| {
+						ui.selectable_value(&mut raster_options.normalization, Normalization::BoundingBox, "Bounding box");
+						ui.selectable_value(&mut raster_options.normalization, Normalization::Centroid, "Centroid");
+						ui.selectable_value(&mut raster_options.normalization, Normalization::FixedScale, "Preserve scale")
+							.on_hover_text("Keep the drawing's size.  The canvas's shorter side spans the output's shorter side.");
+					});
+				ui.checkbox(&mut raster_options.output_grayscale, "Grayscale output");
+				ui.checkbox(&mut raster_options.invert_colors, "Black on white");
+				ui.checkbox(&mut raster_options.output_rgba, "Transparent background (RGBA)")
+					.on_hover_text("Strokes are opaque and anti-aliased edges fade out through alpha.  Black on white picks black strokes.");
+				ui.horizontal(|ui| {
+					ui.checkbox(use_background, "Background image")
+						.on_hover_text("Draw the strokes over this picture in the preview and every saved image.  The result is opaque.");
+					if *use_background {
+						ui.text_edit_singleline(background_path);
+						if ui.button("Load").clicked() {
+							match image::open(background_path.trim()) {
+								Ok(img) => *background_image = Some(img.to_rgb8()),
+								Err(e) => *error_message = Some(format!("Failed to load background {}: {}", background_path, e)),
+							}
+						}
+					}
+				});
+				if *use_background {
+					ui.horizontal(|ui| {
+						ui.checkbox(&mut raster_options.tile_background, "Tile").on_hover_text("Repeat the picture at its own size instead of stretching it to the output");
+						if background_image.is_none() {
+							ui.label("No background loaded.");
+						}
+					});
+				}
+				egui::ComboBox::from_label("Output format")
+					.selected_text(raster_options.output_format.name())
+					.show_ui(ui, |ui| {
+						for format in OutputFormat::ALL {
+							ui.selectable_value(&mut raster_options.output_format, format, format.name());
+						}
+					});
+				if raster_options.output_format == OutputFormat::Jpeg && raster_options.output_rgba {
+					ui.colored_label(egui::Color32::RED, "JPEG has no alpha channel.  Saving RGBA output will fail.");
+				} else if raster_options.output_format == OutputFormat::Jpeg {
+					// Compression ringing is a large fraction of a thin stroke at these sizes.
+					let warning = if raster_options.antialias || (*width).min(*height) < 64 {
+						"JPEG artifacts will noticeably smear small or anti-aliased strokes.  PNG or BMP is recommended."
+					} else {
+						"JPEG is lossy.  Saved images won't exactly match the preview."
+					};
+					ui.colored_label(egui::Color32::from_rgb(255, 160, 0), warning);
+				}
+				ui.horizontal(|ui| {
+					ui.label("Canvas stroke color: ");
+					ui.color_edit_button_srgba(stroke_color);
+				});
+				ui.horizontal(|ui| {
+					ui.label("Filename pattern: ");
+					ui.text_edit_singleline(filename_pattern)
+						.on_hover_text("Tokens: {class}, {n}, {n:05} (zero padded), {timestamp}");
+					if let Some(problem) = filename_pattern_error(filename_pattern) {
+						ui.colored_label(egui::Color32::RED, format!("{}  Saving as {{n}} until it's fixed.", problem));
+					}
+				});
+				ui.horizontal(|ui| {
+					let mut sharded = shard_size.is_some();
+					ui.checkbox(&mut sharded, "Shard classes into subfolders");
+					if sharded {
+						let mut size = shard_size.unwrap_or(1000);
+						ui.add(egui::Slider::new(&mut size, 100..=10000).text("samples per folder"));
+						*shard_size = Some(size);
+					} else {
+						*shard_size = None;
+					}
+				});
+				ui.horizontal(|ui| {
+					ui.checkbox(&mut export_options.augment_flip_h, "Augment: flip-H");
+					ui.checkbox(&mut export_options.augment_flip_v, "flip-V");
+				});
+				ui.add(egui::Slider::new(&mut export_options.rotation_augments, 0..=16).text("rotated copies"))
+					.on_hover_text("Extra images saved with each drawing.  The class counts and targets only count the drawing itself.");
+				if export_options.rotation_augments > 0 {
+					ui.add(egui::Slider::new(&mut export_options.max_rotation_deg, 0.0..=180.0).text("max rotation (deg)"));
+				}
+				ui.add(egui::Slider::new(&mut export_options.simplify_epsilon, 0.0..=0.05).text("stroke file simplification"))
+					.on_hover_text("Drop points that stray less than this from a straight line.  Applies to JSON, time series, SVG and resampled strokes.");
+				ui.checkbox(&mut export_options.export_json, "Also export JSON");
+				if export_options.export_json {
+					ui.add(egui::Slider::new(&mut export_options.stroke_order_shuffles, 0..=8).text("shuffled stroke orders"))
+						.on_hover_text("Extra JSON copies with the strokes reordered.  Each records the original position of its strokes.");
+				}
+				ui.checkbox(&mut export_options.export_time_series, "Also export time series");
+				ui.checkbox(&mut export_options.export_svg, "Also export SVG");
+				ui.checkbox(&mut export_options.export_resampled, "Also export resampled strokes");
+				if export_options.export_resampled {
+					ui.add(egui::Slider::new(&mut export_options.resample_count, 2..=256).text("points per stroke"));
+				}
+
+				ui.separator();
+
+				ui.add(egui::Slider::new(split_train, 0.0..=1.0).text("train fraction"));
+				*split_val = split_val.min(1.0 - *split_train);
+				ui.add(egui::Slider::new(split_val, 0.0..=1.0 - *split_train).text("validation fraction"));
+				ui.horizontal(|ui| {
+					ui.label(format!("test: {:.2}  seed: ", (1.0 - *split_train - *split_val).max(0.0)));
+					ui.add(egui::DragValue::new(split_seed));
+					if ui.button("Split dataset").on_hover_text("Copy each class's samples into train/, val/ and test/ folders in the dataset folder").clicked() {
+						match split_dataset(root_dir, gestures, filename_pattern, (*split_train, *split_val), *split_seed) {
+							Ok(counts) => *status_message = Some(format!("Split into {} train, {} validation and {} test samples", counts[0], counts[1], counts[2])),
+							Err(e) => *error_message = Some(format!("Failed to split dataset: {}", e)),
+						}
+					}
+				});
+				ui.horizontal(|ui| {
+					if ui.button("Export ImageFolder").on_hover_text(format!("Copy every image into {}/<class>/<n>.png, ready for torchvision's ImageFolder", IMAGE_FOLDER_NAME)).clicked() {
+						let resize = Some((*width, *height)).filter(|_| *image_folder_resize);
+						match export_image_folder(root_dir, gestures, resize) {
+							Ok(count) => *status_message = Some(format!("Exported {} images to {}", count, root_dir.join(IMAGE_FOLDER_NAME).display())),
+							Err(e) => *error_message = Some(format!("Failed to export ImageFolder: {}", e)),
+						}
+					}
+					ui.checkbox(image_folder_resize, "Resize to output size");
+				});
+
+				ui.separator();
+
+				// What the saved image will look like at the current settings.
+				ui.label("Preview: ");
+				if let Some(texture) = preview_texture {
+					let scale = (PREVIEW_SIZE / (*width).max(*height).max(1)).max(1);
+					ui.image(*texture, egui::vec2((*width * scale) as f32, (*height * scale) as f32));
+				}
 			});
 
 			ui.with_layout(egui::Layout::bottom_up(egui::Align::LEFT), |ui| {
@@ -141,103 +1210,2199 @@ impl epi::App for GestureDatasetApp {
 			// As soon as a person is done with a stroke, clear it.
 			//egui::stroke_ui(ui, &mut self.stroke, "Stroke");
 
-			if ui.button("Clear Painting").clicked() {
-				drawing.clear();
-			}
-			if ui.button("Save").clicked() {
-				save_image(drawing, label, *sample_count, (*width, *height));
-				*sample_count += 1;
-				drawing.clear();
+			// The class being collected, big enough to notice when Tab changes it.
+			if label.is_empty() {
+				ui.heading("No class selected");
+			} else {
+				ui.heading(format!("Drawing: {}", label)).on_hover_text("Tab / Shift+Tab to switch classes");
 			}
-
-			egui::Frame::dark_canvas(ui.style()).show(ui, |ui| {
-				let (mut response, painter) = ui.allocate_painter(ui.available_size_before_wrap(), egui::Sense::drag());
-				let to_screen = egui::emath::RectTransform::from_to(
-					egui::Rect::from_min_size(egui::Pos2::ZERO, response.rect.square_proportions()),
-					response.rect,
-				);
-				let from_screen = to_screen.inverse();
-
-				if drawing.is_empty() {
-					drawing.push(vec![]);
-				}
-
-				let current_line = drawing.last_mut().unwrap();
-
-				if let Some(pointer_pos) = response.interact_pointer_pos() {
-					let canvas_pos = from_screen * pointer_pos;
-					if current_line.last() != Some(&canvas_pos) {
-						current_line.push(canvas_pos);
-						response.mark_changed();
+			ui.horizontal(|ui| {
+				ui.checkbox(show_recognizer, "Recognize").on_hover_text("Guess each finished drawing's class from the samples saved so far");
+				if *show_recognizer {
+					if ui.button("Load templates").on_hover_text("Also learn from every sample with stroke JSON on disk").clicked() {
+						match load_templates(root_dir, gestures) {
+							// Samples from this session with JSON are on disk too, so this replaces rather than adds.
+							Ok(loaded) => *templates = loaded,
+							Err(e) => *error_message = Some(format!("Failed to load templates: {}", e)),
+						}
+						*predicted_for = (0, 0);
 					}
-				} else if !current_line.is_empty() {
-					drawing.push(vec![]);
-					response.mark_changed();
-				}
-
-				let mut shapes = vec![];
-				for line in drawing.iter() {
-					if line.len() >= 2 {
-						let points: Vec<egui::Pos2> = line.iter().map(|p| to_screen * *p).collect();
-						shapes.push(egui::Shape::line(points, egui::Stroke::new(1.0, egui::Color32::from_rgb(255, 255, 255))));
+					// Only guess once a stroke is finished, and only again when the drawing changes.
+					let key = (drawing.len(), drawing.iter().map(|line| line.len()).sum());
+					if drawing.last().is_none_or(|line| line.is_empty()) && key != *predicted_for {
+						*prediction = recognize(&stroke_positions(drawing), templates).map(|(class, score)| (class.to_owned(), score));
+						*predicted_for = key;
 					}
+					match prediction.as_ref().filter(|_| drawing.iter().any(|line| !line.is_empty())) {
+						Some((class, score)) => ui.label(format!("Looks like: {} ({:.2})", class, score)),
+						None if templates.is_empty() => ui.label("Save some samples to recognize against."),
+						None => ui.label(""),
+					};
 				}
-				painter.extend(shapes);
 			});
-		});
-	}
-}
-
-fn save_image(lines: &Vec<Vec<egui::Pos2>>, class_name: &String, sample_number: u32, raster_size: (u32, u32)) {
-	// Lines will be all over the place, so we want to remap them to the appropriate size.
-	// Find the bounds of the drawing and remap them to the edges of the image.
-	let mut min_x = 1e32;
-	let mut max_x = -1e32;
-	let mut min_y = 1e32;
-	let mut max_y = -1e32;
-	for line in lines.iter() {
-		if line.len() < 2 { continue; }
-		for pt in line {
-			min_x = pt.x.min(min_x);
-			min_y = pt.y.min(min_y);
-			max_x = pt.x.max(max_x);
-			max_y = pt.y.max(max_y);
-		}
-	}
-	max_x += 1e-6;
-	max_y += 1e-6;
-
-	// Draw the pixels.
-	// Normalize to the 0/1 range and set pixels between start and stops.
-	let mut img = image::RgbImage::new(raster_size.0, raster_size.1);
-	for line in lines.iter() {
-		if line.len() < 2 { continue; }
-		for (pt_a, pt_b) in line.iter().zip(line.iter().skip(1)) {
-			let dx = pt_b.x - pt_a.x;
-			let dy = pt_b.y - pt_a.y;
-			let pixel_steps = dx.abs().max(dy.abs()).ceil() as u32;
 
-			for step in 0..pixel_steps {
-				let mut x = pt_a.x + (dx*step as f32 / pixel_steps as f32);
-				let mut y = pt_a.y + (dy*step as f32 / pixel_steps as f32);
-				// Convert the X/Y into the smaller form factor and set the pixel.
-				x = (x - min_x) / (max_x - min_x);
-				y = (y - min_y) / (max_y - min_y);
-
-				let mut pxl = img.get_pixel_mut((x*raster_size.0 as f32) as u32, (y*raster_size.1 as f32) as u32);
-				*pxl = Rgb::from([255, 255, 255]);
+			let mut dismiss_error = false;
+			if let Some(msg) = error_message.as_ref() {
+				ui.horizontal(|ui| {
+					ui.colored_label(egui::Color32::RED, msg);
+					if ui.button("x").clicked() {
+						dismiss_error = true;
+					}
+				});
 			}
-		}
-	}
-
-	// Save the example.
-	let path = format!("{}{}{}.png", class_name, std::path::MAIN_SEPARATOR, sample_number);
-	img.save_with_format(&path, ImageFormat::Png);
-	println!("Saved {}", &path);
+			if dismiss_error {
+				*error_message = None;
+			}
+			let mut dismiss_status = false;
+			if let Some(msg) = status_message.as_ref() {
+				ui.horizontal(|ui| {
+					ui.colored_label(egui::Color32::from_rgb(96, 200, 96), msg);
+					if ui.button("x").clicked() {
+						dismiss_status = true;
+					}
+				});
+			}
+			if dismiss_status {
+				*status_message = None;
+			}
+
+			ui.horizontal(|ui| {
+				if ui.button("Undo").clicked() {
+					undo_requested = true;
+				}
+				if ui.button("Redo").clicked() {
+					redo_requested = true;
+				}
+				if ui.button("Clear Painting").clicked() {
+					clear_requested = true;
+				}
+				if ui.button("Duplicate Stroke").on_hover_text("Copy the last stroke, shifted slightly (D)").clicked() {
+					duplicate_requested = true;
+				}
+				let stroke_count = drawing.iter().filter(|line| !line.is_empty()).count();
+				match max_strokes {
+					Some(max) => ui.label(format!("Strokes: {} / {}", stroke_count, max)),
+					None => ui.label(format!("Strokes: {}", stroke_count)),
+				};
+				if over_point_limit && !*auto_thin_points {
+					ui.colored_label(egui::Color32::YELLOW, format!("Points: {} (over the limit)", point_count))
+						.on_hover_text("Save or clear soon, or turn on auto-thin in the settings.");
+				} else {
+					ui.label(format!("Points: {}", point_count));
+				}
+				ui.label("Right-click or shift-click a stroke to delete it.");
+			});
+			// The class name box doubles as the current class, so only count it as switching once it names a class.
+			if *clear_on_class_switch && *label != class_before && gestures.contains(label) {
+				clear_requested = true;
+			}
+			if clear_requested {
+				// Keep the strokes around so an accidental clear can be undone.
+				cleared_strokes.clear();
+				cleared_strokes.extend(drawing.drain(..).filter(|line| !line.is_empty()));
+				redo_stack.clear();
+			}
+			ui.horizontal(|ui| {
+				ui.checkbox(eraser_mode, "Eraser");
+				if *eraser_mode {
+					ui.add(egui::Slider::new(eraser_radius, 2.0..=50.0).text("eraser radius"));
+				}
+			});
+			ui.horizontal(|ui| {
+				ui.checkbox(show_grid, "Grid");
+				if *show_grid {
+					ui.add(egui::Slider::new(grid_cells, 2..=16).text("cells"));
+				}
+				ui.checkbox(onion_skin, "Onion skin");
+				ui.checkbox(velocity_color, "Color by speed");
+				ui.checkbox(stroke_colors, "Number strokes").on_hover_text("Color and number each stroke in the order it was drawn");
+				ui.checkbox(show_stroke_direction, "Direction arrows").on_hover_text("Mark which way each stroke was drawn");
+				ui.checkbox(show_pixel_ruler, "Pixel ruler");
+				ui.checkbox(show_debug_overlay, "Debug overlay").on_hover_text("Show the canvas size, its screen transform, and the pointer's canvas coordinates");
+				ui.checkbox(show_text_guide, "Trace class name");
+				ui.label(format!("Zoom: {:.1}x", *canvas_zoom)).on_hover_text("Scroll over the canvas to zoom, middle-drag to pan.");
+				if ui.button("Reset view").clicked() {
+					*canvas_zoom = 1.0;
+					*canvas_pan = egui::Vec2::ZERO;
+				}
+				ui.checkbox(canvas_detached, "Detach canvas");
+			});
+			ui.horizontal(|ui| {
+				ui.label("Reference image: ");
+				ui.text_edit_singleline(reference_path_edit);
+				if ui.button("Load reference image").clicked() {
+					match image::open(reference_path_edit.trim()) {
+						Ok(img) => {
+							let img = img.to_rgba8();
+							if let Some((old_texture, _)) = reference_texture.take() {
+								frame.free_texture(old_texture);
+							}
+							let size = egui::vec2(img.width() as f32, img.height() as f32);
+							let texture = frame.alloc_texture(epi::Image::from_rgba_unmultiplied([img.width() as usize, img.height() as usize], img.as_raw()));
+							*reference_texture = Some((texture, size));
+						}
+						Err(e) => *error_message = Some(format!("Failed to load reference image: {}", e)),
+					}
+				}
+				if reference_texture.is_some() && ui.button("Clear reference").clicked() {
+					if let Some((old_texture, _)) = reference_texture.take() {
+						frame.free_texture(old_texture);
+					}
+				}
+			});
+			if undo_requested {
+				undo_stroke(drawing, cleared_strokes, redo_stack);
+			}
+			if redo_requested {
+				redo_stroke(drawing, redo_stack);
+			}
+			let stroke_count = drawing.iter().filter(|line| !line.is_empty()).count();
+			if duplicate_requested && max_strokes.is_none_or(|max| stroke_count < max as usize) && duplicate_last_stroke(drawing, egui::vec2(0.03, 0.03)) {
+				// Same as finishing a stroke by hand.
+				cleared_strokes.clear();
+				redo_stack.clear();
+			}
+			let mut save_negative_requested = false;
+			let mut generate_negatives_requested = false;
+			ui.horizontal(|ui| {
+				if ui.button("Save").clicked() {
+					save_requested = true;
+				}
+				save_negative_requested = ui.button("Save as Negative")
+					.on_hover_text(format!("Save the canvas, even if blank, to the {} class.", NEGATIVE_CLASS))
+					.clicked();
+				generate_negatives_requested = ui.button("Generate negatives").clicked();
+				ui.add(egui::Slider::new(negatives_to_generate, 1..=500).text("random scribbles"));
+			});
+			ui.horizontal(|ui| {
+				ui.label("Tags:");
+				ui.text_edit_singleline(sample_tags)
+					.on_hover_text("Comma-separated, e.g. \"left-handed, fast\".  Written to a .tags.json file with each sample and included in manifest.csv.");
+			});
+			ui.horizontal(|ui| {
+				ui.checkbox(clear_after_save, "Clear after save")
+					.on_hover_text("Turn off to keep drawing on the same canvas and save each step as another sample.");
+				ui.checkbox(clear_on_class_switch, "Clear when switching class");
+				ui.checkbox(round_robin, "Round-robin on save").on_hover_text("Advance to the next class after each save, wrapping at the end");
+				let mut quick_draw = auto_save_idle_secs.is_some();
+				ui.checkbox(&mut quick_draw, "Auto-save when idle");
+				if quick_draw {
+					let mut secs = auto_save_idle_secs.unwrap_or(1.5);
+					ui.add(egui::Slider::new(&mut secs, 0.5..=10.0).text("seconds"));
+					*auto_save_idle_secs = Some(secs);
+					if let Some(remaining) = auto_save_in.filter(|remaining| *remaining > 0.0) {
+						ui.label(format!("Saving in {:.1} s", remaining));
+					}
+				} else {
+					*auto_save_idle_secs = None;
+				}
+			});
+			// Whatever happens to this save, the countdown is done until something new is drawn.
+			if save_requested || save_negative_requested {
+				*last_activity_time = None;
+			}
+			// Stray clicks make blank or near-blank images.  Refuse those before they reach the dataset.
+			if save_requested {
+				let length = total_stroke_length(drawing);
+				if drawing.iter().all(|line| line.is_empty()) || length < *min_stroke_length {
+					*error_message = Some(format!("Drawing is too short to save ({:.3} < {:.3}).", length, *min_stroke_length));
+					save_requested = false;
+				} else if !gestures.contains(label) {
+					// The class box may hold a half-typed or never-added name.  Only classes added with "+" get samples.
+					*error_message = Some(if label.trim().is_empty() {
+						"Pick a class to save to.".to_owned()
+					} else {
+						format!("'{}' isn't a class yet.  Add it with \"+\" first.", label)
+					});
+					save_requested = false;
+				}
+			}
+
+			// Negatives skip the length check and staging: a blank or stray canvas is exactly what they're for.
+			let target_class = if save_negative_requested { NEGATIVE_CLASS.to_owned() } else { label.clone() };
+			if save_requested && *staging_mode && !save_negative_requested {
+				// Park the drawing for later.  Empty canvases aren't worth staging.
+				drawing.retain(|line| !line.is_empty());
+				if !drawing.is_empty() {
+					staged.push((target_class.clone(), std::mem::take(drawing), parse_tags(sample_tags)));
+					cleared_strokes.clear();
+					redo_stack.clear();
+				}
+			} else if save_requested || save_negative_requested {
+				let hash = average_hash(&rasterize(&stroke_positions(drawing), &stroke_pressures(drawing), (*width, *height), raster_options));
+				let duplicate = duplicate_distance.is_some_and(|distance| {
+					recent_hashes.get(&target_class).is_some_and(|recent| recent.iter().any(|h| (h ^ hash).count_ones() <= distance))
+				});
+				if duplicate && *skip_duplicates {
+					*error_message = Some(format!("Not saved: nearly identical to a recent {} sample.", target_class));
+				} else {
+					let saved = claim_sample_number(sample_counts, root_dir, &target_class, filename_pattern)
+						.and_then(|n| SampleLocation::new(root_dir, &target_class, n, filename_pattern, *shard_size))
+						.and_then(|location| {
+							let files = save_sample(drawing, &location, (*width, *height), raster_options, export_options, background_image.as_ref().filter(|_| *use_background), &parse_tags(sample_tags))?;
+							Ok((location, files))
+						});
+					match saved {
+						Ok((location, files)) => {
+							let sample_number = location.sample_number;
+							let template = Template::new(&target_class, &stroke_positions(drawing));
+							templates.extend(template.clone());
+							session_log.push(SavedSample { location, files, saved_at: unix_timestamp(), template, hash: Some(hash) });
+							sample_counts.insert(target_class.clone(), sample_number + 1);
+							*saved_counts.entry(target_class.clone()).or_insert(0) += 1;
+							// The negative class is the only one that comes into being by saving to it.
+							if save_negative_requested && !gestures.contains(&target_class) {
+								gestures.push(target_class.clone());
+							}
+							let recent = recent_hashes.entry(target_class.clone()).or_default();
+							recent.push_back(hash);
+							if recent.len() > RECENT_HASH_COUNT {
+								recent.pop_front();
+							}
+							*previous_sample = stroke_positions(drawing);
+							// Negatives are saved in between, so they don't move the rotation along.
+							if *round_robin && !save_negative_requested {
+								let rotation: Vec<&String> = gestures.iter().filter(|g| *g != NEGATIVE_CLASS).collect();
+								if let Some(idx) = rotation.iter().position(|g| **g == target_class) {
+									*label = rotation[(idx + 1) % rotation.len()].clone();
+								}
+							}
+							if *clear_after_save {
+								drawing.clear();
+								cleared_strokes.clear();
+								redo_stack.clear();
+							}
+							*error_message = if duplicate {
+								Some(format!("Saved, but it's nearly identical to a recent {} sample.", target_class))
+							} else {
+								None
+							};
+						}
+						Err(e) => {
+							// Keep the drawing and the counter so the user can fix the problem and retry.
+							*error_message = Some(format!("Failed to save sample: {}", e));
+						}
+					}
+				}
+			}
+
+			if generate_negatives_requested {
+				let mut rng = rand::thread_rng();
+				let negative = NEGATIVE_CLASS.to_owned();
+				if !gestures.contains(&negative) {
+					gestures.push(negative.clone());
+				}
+				for _ in 0..*negatives_to_generate {
+					let scribble = random_scribble(&mut rng);
+					let saved = claim_sample_number(sample_counts, root_dir, &negative, filename_pattern)
+						.and_then(|n| SampleLocation::new(root_dir, &negative, n, filename_pattern, *shard_size))
+						.and_then(|location| {
+							let files = save_sample(&scribble, &location, (*width, *height), raster_options, export_options, background_image.as_ref().filter(|_| *use_background), &[])?;
+							Ok((location, files))
+						});
+					match saved {
+						Ok((location, files)) => {
+							let sample_number = location.sample_number;
+							session_log.push(SavedSample { location, files, saved_at: unix_timestamp(), template: None, hash: None });
+							sample_counts.insert(negative.clone(), sample_number + 1);
+							*saved_counts.entry(negative.clone()).or_insert(0) += 1;
+						}
+						Err(e) => {
+							*error_message = Some(format!("Failed to save generated negative: {}", e));
+							break;
+						}
+					}
+				}
+			}
+
+			// Confirm saves in the window, since stdout is usually nowhere to be seen.
+			let now = ctx.input().time;
+			if session_log.len() > *seen_log_len {
+				*last_save_time = now;
+			}
+			*seen_log_len = session_log.len();
+			if let Some(last) = session_log.last() {
+				ui.horizontal(|ui| {
+					if now - *last_save_time < SAVED_FLASH_SECS {
+						ui.colored_label(egui::Color32::from_rgb(96, 200, 96), "Saved ✓");
+						// Nothing else may redraw the window in time to take the flash down.
+						match max_timed_fps {
+							Some(_) => request_repaint_after(frame, scheduled_repaint, SAVED_FLASH_SECS - (now - *last_save_time)),
+							None => ctx.request_repaint(),
+						}
+					}
+					ui.label(format!("Last saved: {}", last.files[0].display()));
+				});
+			}
+
+			ui.checkbox(staging_mode, "Staging mode: Save queues samples instead of writing them");
+			if !staged.is_empty() {
+				egui::CollapsingHeader::new(format!("Staged samples ({})", staged.len())).default_open(true).show(ui, |ui| {
+					let mut to_discard: Option<usize> = None;
+					let mut to_edit: Option<usize> = None;
+					for (idx, (class_name, sample, _)) in staged.iter().enumerate() {
+						ui.horizontal(|ui| {
+							ui.label(format!("#{}: {}, {} stroke(s)", idx + 1, class_name, sample.len()));
+							// Only pull a sample back onto the canvas if that won't throw away what's there.
+							let canvas_empty = drawing.iter().all(|line| line.is_empty());
+							if ui.add_enabled(canvas_empty, egui::Button::new("Edit")).clicked() {
+								to_edit = Some(idx);
+							}
+							if ui.button("x").clicked() {
+								to_discard = Some(idx);
+							}
+						});
+					}
+					if let Some(idx) = to_discard {
+						staged.remove(idx);
+					}
+					if let Some(idx) = to_edit {
+						// Editing picks the sample's class and tags again, so saving it goes back where it came from.
+						let (class_name, sample, tags) = staged.remove(idx);
+						*label = class_name;
+						*sample_tags = tags.join(", ");
+						*drawing = sample;
+						drawing.push(vec![]);
+					}
+
+					if ui.button("Save All").clicked() {
+						// Write them in order, stopping at the first failure so nothing unsaved is lost.
+						while let Some((class_name, sample, tags)) = staged.first() {
+							let class_name = class_name.clone();
+							let saved = claim_sample_number(sample_counts, root_dir, &class_name, filename_pattern)
+								.and_then(|n| SampleLocation::new(root_dir, &class_name, n, filename_pattern, *shard_size))
+								.and_then(|location| {
+									let files = save_sample(sample, &location, (*width, *height), raster_options, export_options, background_image.as_ref().filter(|_| *use_background), tags)?;
+									Ok((location, files))
+								});
+							match saved {
+								Ok((location, files)) => {
+									let sample_number = location.sample_number;
+									*previous_sample = stroke_positions(&staged.remove(0).1);
+									let template = Template::new(&class_name, previous_sample);
+									templates.extend(template.clone());
+									session_log.push(SavedSample { location, files, saved_at: unix_timestamp(), template, hash: None });
+									sample_counts.insert(class_name.clone(), sample_number + 1);
+									*saved_counts.entry(class_name.clone()).or_insert(0) += 1;
+									*error_message = None;
+								}
+								Err(e) => {
+									*error_message = Some(format!("Failed to save staged sample: {}", e));
+									break;
+								}
+							}
+						}
+					}
+				});
+			}
+
+			// Saved to the wrong class?  Move the last sample over instead of redrawing it.
+			let mut move_requested = false;
+			if let Some(last) = session_log.last() {
+				ui.horizontal(|ui| {
+					egui::ComboBox::from_id_source("move_target")
+						.selected_text(move_target.as_str())
+						.show_ui(ui, |ui| {
+							for g in gestures.iter().filter(|g| **g != last.location.class_name) {
+								ui.selectable_value(move_target, g.clone(), g);
+							}
+						});
+					let valid_target = !move_target.is_empty() && *move_target != last.location.class_name;
+					move_requested = ui.add_enabled(valid_target, egui::Button::new("Move last sample here")).clicked();
+				});
+			}
+			if move_requested {
+				let last = session_log.pop().unwrap();
+				let moved = claim_sample_number(sample_counts, root_dir, move_target, filename_pattern)
+					.and_then(|n| SampleLocation::new(root_dir, move_target, n, filename_pattern, *shard_size))
+					.and_then(|to| {
+						let files = move_sample(&last, &to)?;
+						Ok((to, files))
+					});
+				match moved {
+					Ok((to, files)) => {
+						let sample_number = to.sample_number;
+						if let Some(count) = saved_counts.get_mut(&last.location.class_name) {
+							*count = count.saturating_sub(1);
+						}
+						*saved_counts.entry(move_target.clone()).or_insert(0) += 1;
+						sample_counts.insert(move_target.clone(), sample_number + 1);
+						// Otherwise the recognizer keeps learning the very mistake that was just corrected.
+						if let Some(old) = &last.template {
+							if let Some(learned) = templates.iter_mut().find(|t| *t == old) {
+								learned.class_name = move_target.clone();
+							}
+						}
+						let template = last.template.map(|mut template| {
+							template.class_name = move_target.clone();
+							template
+						});
+						if let Some(hash) = last.hash {
+							if let Some(recent) = recent_hashes.get_mut(&last.location.class_name) {
+								recent.retain(|h| *h != hash);
+							}
+							let recent = recent_hashes.entry(move_target.clone()).or_default();
+							recent.push_back(hash);
+							if recent.len() > RECENT_HASH_COUNT {
+								recent.pop_front();
+							}
+						}
+						session_log.push(SavedSample { location: to, files, saved_at: last.saved_at, template, hash: last.hash });
+						*error_message = None;
+					}
+					Err(e) => {
+						*error_message = Some(format!("Failed to move sample: {}", e));
+						session_log.push(last);
+					}
+				}
+			}
+
+			if !session_log.is_empty() {
+				egui::CollapsingHeader::new(format!("Saved this session ({})", session_log.len())).show(ui, |ui| {
+					let mut to_delete: Option<usize> = None;
+					egui::ScrollArea::vertical().max_height(120.0).show(ui, |ui| {
+						// Newest first, since that's usually what needs fixing.
+						for (idx, sample) in session_log.iter().enumerate().rev() {
+							ui.horizontal(|ui| {
+								let file_name = sample.files[0].file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+								ui.label(format!("{}  {}  {}", format_time_of_day(sample.saved_at), sample.location.class_name, file_name));
+								if ui.button("Open").clicked() {
+									if let Err(e) = open_with_system(&sample.files[0]) {
+										*error_message = Some(format!("Failed to open {}: {}", sample.files[0].display(), e));
+									}
+								}
+								if let Some(folder) = sample.files[0].parent() {
+									if ui.button("📂").on_hover_text("Open the folder holding this sample").clicked() {
+										if let Err(e) = open_with_system(folder) {
+											*error_message = Some(format!("Failed to open {}: {}", folder.display(), e));
+										}
+									}
+								}
+								if ui.button("🗑").on_hover_text("Delete this sample's files").clicked() {
+									to_delete = Some(idx);
+								}
+							});
+						}
+					});
+					if let Some(idx) = to_delete {
+						// Files already gone are fine.  Anything else keeps the entry so it can be retried.
+						let failed = session_log[idx].files.iter()
+							.map(std::fs::remove_file)
+							.find(|result| result.as_ref().is_err_and(|e| e.kind() != std::io::ErrorKind::NotFound));
+						match failed {
+							Some(Err(e)) => *error_message = Some(format!("Failed to delete sample {}: {}", session_log[idx].location.sample_number, e)),
+							_ => {
+								let removed = session_log.remove(idx);
+								if let Some(count) = saved_counts.get_mut(&removed.location.class_name) {
+									*count = count.saturating_sub(1);
+								}
+								// Forget the drawing too, so it isn't recognized or flagged as a duplicate from a deleted file.
+								if let Some(pos) = removed.template.as_ref().and_then(|old| templates.iter().position(|t| t == old)) {
+									templates.remove(pos);
+								}
+								if let (Some(hash), Some(recent)) = (removed.hash, recent_hashes.get_mut(&removed.location.class_name)) {
+									recent.retain(|h| *h != hash);
+								}
+							}
+						}
+					}
+				});
+			}
+
+			// The canvas lives either at the bottom of this panel or in its own window, which can be dragged onto a tablet's screen.
+			let mut canvas_ui = |ui: &mut egui::Ui| {
+				egui::Frame::dark_canvas(ui.style()).show(ui, |ui| {
+					// Optionally shrink the canvas to the output's aspect ratio, so what's drawn is shaped like what's saved.
+					let available = ui.available_size_before_wrap();
+					let canvas_size = if *match_canvas_aspect && *width > 0 && *height > 0 {
+						let aspect = *width as f32 / *height as f32;
+						let canvas_width = available.x.min(available.y * aspect);
+						egui::vec2(canvas_width, canvas_width / aspect)
+					} else {
+						available
+					};
+					// Clicks are sensed too, so a right-click or shift-click can pick out a single stroke.
+					// Everything here is in logical points, which egui has already converted from physical pixels.  The pointer,
+					// the canvas rect and its transforms all share that space, so display scaling doesn't shift the strokes.
+					let (mut response, painter) = ui.allocate_painter(canvas_size, egui::Sense::click_and_drag());
+					let full_canvas = response.rect.square_proportions();
+
+					// Zoom around the pointer, so the spot under it stays put, and pan by middle-dragging.
+					if let Some(hover_pos) = response.hover_pos() {
+						let scroll = ctx.input().scroll_delta.y;
+						let anchor = (hover_pos - response.rect.min) / response.rect.size();
+						if scroll != 0.0 {
+							let anchor_canvas = *canvas_pan + anchor * (full_canvas / *canvas_zoom);
+							*canvas_zoom = (*canvas_zoom * (scroll * 0.002).exp()).clamp(1.0, 8.0);
+							*canvas_pan = anchor_canvas - anchor * (full_canvas / *canvas_zoom);
+						}
+						if ctx.input().pointer.middle_down() {
+							*canvas_pan -= ctx.input().pointer.delta() / response.rect.size() * (full_canvas / *canvas_zoom);
+						}
+					}
+					let visible = full_canvas / *canvas_zoom;
+					*canvas_pan = canvas_pan.max(egui::Vec2::ZERO).min(full_canvas - visible);
+
+					// The largest box with the output's aspect ratio that fits the canvas, in canvas units.
+					let output_box = if *width > 0 && *height > 0 {
+						let aspect = *width as f32 / *height as f32;
+						let size = if full_canvas.x / full_canvas.y > aspect { egui::vec2(full_canvas.y * aspect, full_canvas.y) } else { egui::vec2(full_canvas.x, full_canvas.x / aspect) };
+						Some(egui::Rect::from_center_size((full_canvas / 2.0).to_pos2(), size))
+					} else {
+						None
+					};
+					let drawable = output_box.filter(|_| *bounded_region);
+
+					let to_screen = egui::emath::RectTransform::from_to(
+						egui::Rect::from_min_size(egui::Pos2::ZERO + *canvas_pan, visible),
+						response.rect,
+					);
+					let from_screen = to_screen.inverse();
+
+					if drawing.is_empty() {
+						drawing.push(vec![]);
+					}
+
+					// The eraser size is given in screen points, but strokes live in canvas units.
+					let eraser_canvas_radius = *eraser_radius / response.rect.width().min(response.rect.height()) / *canvas_zoom;
+
+					let pick_canvas_radius = 8.0 / response.rect.width().min(response.rect.height()) / *canvas_zoom;
+					let shift_held = ctx.input().modifiers.shift;
+					// At the limit, the trailing empty line can't be started.  A stroke already in progress can still be finished.
+					let stroke_count = drawing.iter().filter(|line| !line.is_empty()).count();
+					let at_stroke_limit = max_strokes.is_some_and(|max| stroke_count >= max as usize) && drawing.last().is_some_and(|line| line.is_empty());
+
+					let current_line = drawing.last_mut().unwrap();
+
+					if response.secondary_clicked() || (shift_held && response.clicked()) {
+						if let Some(pointer_pos) = response.interact_pointer_pos() {
+							if let Some(idx) = stroke_near(drawing, from_screen * pointer_pos, pick_canvas_radius) {
+								drawing.remove(idx);
+								redo_stack.clear();
+								response.mark_changed();
+							}
+						}
+					} else if *eraser_mode {
+						if let Some(pointer_pos) = response.interact_pointer_pos() {
+							if erase_near(drawing, from_screen * pointer_pos, eraser_canvas_radius) {
+								redo_stack.clear();
+								response.mark_changed();
+							}
+						}
+					} else if let Some(pointer_pos) = response.interact_pointer_pos().filter(|_| !shift_held && !at_stroke_limit && !*touch_gesture && ctx.input().pointer.primary_down()) {
+						let canvas_pos = from_screen * pointer_pos;
+						let in_bounds = drawable.is_none_or(|rect| rect.contains(canvas_pos));
+						let far_enough = current_line.last().is_none_or(|p| p.pos != canvas_pos && p.pos.distance(canvas_pos) >= *min_point_spacing);
+						if in_bounds && far_enough {
+							let now = ctx.input().time;
+							if current_line.is_empty() {
+								*stroke_start_time = now;
+							}
+							current_line.push(StrokePoint { pos: canvas_pos, t: ((now - *stroke_start_time) * 1000.0) as f32, pressure: pointer_pressure(ctx, active_touches.first().copied()) });
+							*last_activity_time = Some(now);
+							response.mark_changed();
+						}
+					} else if !current_line.is_empty() {
+						// A stroke was just completed, so anything undone before it can no longer be redone.
+						drawing.push(vec![]);
+						cleared_strokes.clear();
+						redo_stack.clear();
+						response.mark_changed();
+					}
+
+					let mut shapes = vec![];
+					if let Some((texture, size)) = reference_texture {
+						// Fit the image inside the canvas without distorting it.  It zooms and pans with the strokes.
+						let scale = (full_canvas.x / size.x).min(full_canvas.y / size.y);
+						let image_rect = egui::Rect::from_center_size((full_canvas / 2.0).to_pos2(), *size * scale);
+						let screen_rect = egui::Rect::from_min_max(to_screen * image_rect.min, to_screen * image_rect.max);
+						let mut mesh = egui::epaint::Mesh::with_texture(*texture);
+						mesh.add_rect_with_uv(screen_rect, egui::Rect::from_min_max(egui::Pos2::ZERO, egui::pos2(1.0, 1.0)), egui::Color32::from_white_alpha(64));
+						shapes.push(egui::Shape::mesh(mesh));
+					}
+					if *show_grid {
+						// The grid divides the whole canvas, so it zooms and pans with the strokes.
+						let grid_stroke = egui::Stroke::new(1.0, egui::Color32::from_gray(70));
+						for i in 1..*grid_cells {
+							let t = i as f32 / *grid_cells as f32;
+							let (x, y) = (full_canvas.x * t, full_canvas.y * t);
+							shapes.push(egui::Shape::line_segment([to_screen * egui::pos2(x, 0.0), to_screen * egui::pos2(x, full_canvas.y)], grid_stroke));
+							shapes.push(egui::Shape::line_segment([to_screen * egui::pos2(0.0, y), to_screen * egui::pos2(full_canvas.x, y)], grid_stroke));
+						}
+					}
+					if *show_text_guide && !label.is_empty() {
+						// Fill the area that gets saved, so the glyph lands where a drawing of it would.
+						let guide = output_box.unwrap_or_else(|| egui::Rect::from_min_size(egui::Pos2::ZERO, full_canvas));
+						let screen_rect = egui::Rect::from_min_max(to_screen * guide.min, to_screen * guide.max);
+						shapes.push(egui::Shape::mesh(scaled_text_mesh(ctx, label, screen_rect.shrink(screen_rect.height() * 0.05), stroke_color.linear_multiply(0.15))));
+					}
+					if let Some(rect) = drawable {
+						let screen_rect = egui::Rect::from_min_max(to_screen * rect.min, to_screen * rect.max);
+						shapes.push(egui::Shape::rect_stroke(screen_rect, 0.0, egui::Stroke::new(1.0, egui::Color32::from_gray(110))));
+					}
+					if *onion_skin {
+						let ghost_color = stroke_color.linear_multiply(0.25);
+						for line in previous_sample.iter() {
+							if line.len() >= 2 {
+								shapes.push(egui::Shape::line(line.iter().map(|p| to_screen * *p).collect(), egui::Stroke::new(1.0, ghost_color)));
+							} else if line.len() == 1 {
+								shapes.push(egui::Shape::circle_filled(to_screen * line[0], 1.0, ghost_color));
+							}
+						}
+					}
+					// Empty lines are placeholders for the next stroke, so they don't take a number.
+					for (idx, line) in drawing.iter().filter(|line| !line.is_empty()).enumerate() {
+						let stroke_color = if *stroke_colors { &STROKE_PALETTE[idx % STROKE_PALETTE.len()] } else { &*stroke_color };
+						if line.len() >= 2 && *velocity_color && !*stroke_colors {
+							for w in line.windows(2) {
+								let color = speed_color(w[0], w[1]);
+								shapes.push(egui::Shape::line_segment([to_screen * w[0].pos, to_screen * w[1].pos], egui::Stroke::new(1.0, color)));
+							}
+						} else if line.len() >= 2 {
+							let points: Vec<egui::Pos2> = line.iter().map(|p| to_screen * p.pos).collect();
+							shapes.push(egui::Shape::line(points, egui::Stroke::new(1.0, *stroke_color)));
+						} else if line.len() == 1 {
+							shapes.push(egui::Shape::circle_filled(to_screen * line[0].pos, 1.0, *stroke_color));
+						}
+						if *show_stroke_direction {
+							let points: Vec<egui::Pos2> = line.iter().map(|p| to_screen * p.pos).collect();
+							shapes.extend(direction_arrows(&points, 40.0, 6.0, egui::Stroke::new(1.5, *stroke_color)));
+						}
+					}
+					if *eraser_mode {
+						if let Some(hover_pos) = response.hover_pos() {
+							shapes.push(egui::Shape::circle_stroke(hover_pos, *eraser_radius, egui::Stroke::new(1.0, egui::Color32::from_rgb(255, 96, 96))));
+						}
+					}
+					painter.extend(shapes);
+					if *stroke_colors {
+						for (idx, line) in drawing.iter().filter(|line| !line.is_empty()).enumerate() {
+							painter.text(to_screen * line[0].pos, egui::Align2::RIGHT_BOTTOM, format!("{}", idx + 1), egui::TextStyle::Small, STROKE_PALETTE[idx % STROKE_PALETTE.len()]);
+						}
+					}
+
+					if let Some(output_box) = output_box.filter(|_| *show_pixel_ruler) {
+						// One output pixel when the output box fills the canvas.  Normalization may scale the drawing further.
+						let pixel_points = output_box.width() / *width as f32 * response.rect.width() / visible.x;
+						let corner = response.rect.left_bottom() + egui::vec2(8.0, -8.0);
+						let ruler = egui::Rect::from_min_size(corner - egui::vec2(0.0, pixel_points), egui::Vec2::splat(pixel_points));
+						painter.rect_filled(ruler, 0.0, egui::Color32::from_white_alpha(96));
+						painter.text(
+							ruler.right_bottom() + egui::vec2(6.0, 0.0),
+							egui::Align2::LEFT_BOTTOM,
+							format!("1 output px ≈ {:.1} screen px", pixel_points * ctx.pixels_per_point()),
+							egui::TextStyle::Small,
+							egui::Color32::from_gray(160),
+						);
+					}
+
+					if *show_debug_overlay {
+						// Canvas units run 0 to 1 along the canvas's shorter side, which `square_proportions` sets.
+						let mut lines = vec![
+							format!("rect: {:.1} x {:.1} pt at ({:.1}, {:.1})", response.rect.width(), response.rect.height(), response.rect.left(), response.rect.top()),
+							format!("canvas: {:.3} x {:.3} units, visible {:.3} x {:.3}", full_canvas.x, full_canvas.y, visible.x, visible.y),
+							format!("to_screen scale: {:.1} x {:.1} pt per unit", to_screen.scale().x, to_screen.scale().y),
+							format!("from_screen scale: {:.5} x {:.5} units per pt", from_screen.scale().x, from_screen.scale().y),
+						];
+						lines.push(match response.hover_pos() {
+							Some(pos) => {
+								let canvas_pos = from_screen * pos;
+								format!("pointer: ({:.3}, {:.3}) units, ({:.3}, {:.3}) of the canvas", canvas_pos.x, canvas_pos.y, canvas_pos.x / full_canvas.x, canvas_pos.y / full_canvas.y)
+							}
+							None => "pointer: off the canvas".to_owned(),
+						});
+						painter.text(
+							response.rect.left_top() + egui::vec2(8.0, 8.0),
+							egui::Align2::LEFT_TOP,
+							lines.join("\n"),
+							egui::TextStyle::Monospace,
+							egui::Color32::from_rgb(255, 255, 0),
+						);
+					}
+				});
+			};
+			if *canvas_detached {
+				let mut open = true;
+				egui::Window::new("Canvas").open(&mut open).resizable(true).default_size([600.0, 600.0]).show(ctx, |ui| canvas_ui(ui));
+				// Closing the window puts the canvas back in the panel.
+				*canvas_detached = open;
+			} else {
+				canvas_ui(ui);
+			}
+		});
+
+		// Deleting a class needs confirmation, since it can take a whole directory of samples with it.
+		if let Some(class_name) = pending_delete.clone() {
+			let mut close = false;
+			egui::Window::new("Delete gesture class").collapsible(false).resizable(false).show(ctx, |ui| {
+				ui.label(format!("Remove '{}' from the list of classes?", class_name));
+				ui.horizontal(|ui| {
+					let remove_only = ui.button("Remove from list").clicked();
+					let delete_files = ui.button("Delete folder and samples").clicked();
+					if remove_only || delete_files {
+						let deleted = if delete_files { delete_class_dir(root_dir, &class_name) } else { Ok(()) };
+						match deleted {
+							Ok(()) => {
+								if delete_files {
+									sample_counts.remove(&class_name);
+									saved_counts.remove(&class_name);
+								}
+								gestures.retain(|g| *g != class_name);
+								if *label == class_name {
+									label.clear();
+								}
+							}
+							Err(e) => {
+								// Leave the class in place so it's clear its files are still on disk.
+								*error_message = Some(format!("Failed to delete '{}': {}", class_name, e));
+							}
+						}
+						close = true;
+					}
+					if ui.button("Cancel").clicked() {
+						close = true;
+					}
+				});
+			});
+			if close {
+				*pending_delete = None;
+			}
+		}
+
+		if let Some(class_name) = pending_counter_reset.clone() {
+			let mut close = false;
+			egui::Window::new("Reset sample counter").collapsible(false).resizable(false).show(ctx, |ui| {
+				ui.label(format!("Number new '{}' samples from 0 again?  Numbers that already have files are skipped, so nothing is overwritten.", class_name));
+				ui.horizontal(|ui| {
+					if ui.button("Reset").clicked() {
+						sample_counts.insert(class_name.clone(), 0);
+						close = true;
+					}
+					if ui.button("Cancel").clicked() {
+						close = true;
+					}
+				});
+			});
+			if close {
+				*pending_counter_reset = None;
+			}
+		}
+
+		if *show_stats {
+			let mut refresh = false;
+			egui::Window::new("Dataset stats").open(show_stats).show(ctx, |ui| {
+				if let Some(stats) = dataset_stats.as_ref() {
+					let total: usize = stats.per_class.iter().map(|(_, count)| count).sum();
+					ui.label(format!("Total samples: {}", total));
+					if let (Some(min), Some(max)) = (stats.per_class.iter().map(|(_, c)| *c).min(), stats.per_class.iter().map(|(_, c)| *c).max()) {
+						let mean = total as f32 / stats.per_class.len() as f32;
+						ui.label(format!("Per class: min {}, max {}, mean {:.1}", min, max, mean));
+					}
+					ui.label(format!("Disk usage: {}", format_bytes(stats.disk_bytes)));
+					ui.separator();
+					for (class_name, count) in &stats.per_class {
+						ui.label(format!("{}: {}", class_name, count));
+					}
+				}
+				refresh = ui.button("Refresh").clicked();
+			});
+			if refresh {
+				*dataset_stats = Some(compute_dataset_stats(root_dir, gestures, filename_pattern));
+			}
+		}
+
+		// Rebuild the preview from this frame's drawing.  Only upload a new texture when the pixels actually changed.
+		if *width > 0 && *height > 0 {
+			let mut preview = rasterize(&stroke_positions(drawing), &stroke_pressures(drawing), (*width, *height), raster_options);
+			if let Some(background) = background_image.as_ref().filter(|_| *use_background) {
+				preview = composite_background(&preview, background, raster_options);
+			}
+			let preview = preview.to_rgba8();
+			let pixels_per_point = ctx.pixels_per_point();
+			if preview_image.as_ref() != Some(&preview) || *preview_pixels_per_point != pixels_per_point {
+				// Scale up with nearest neighbor so individual output pixels stay crisp.
+				// The preview is laid out in points but textures are drawn in physical pixels, so enlarge to the screen's
+				// resolution.  Otherwise a high-DPI display stretches the texture again and blurs the pixels together.
+				let scale = (PREVIEW_SIZE / (*width).max(*height)).max(1);
+				let texel_scale = ((scale as f32 * pixels_per_point).round() as u32).max(1);
+				let enlarged = image::imageops::resize(&preview, *width * texel_scale, *height * texel_scale, image::imageops::FilterType::Nearest);
+				*preview_pixels_per_point = pixels_per_point;
+				if let Some(old_texture) = preview_texture.take() {
+					frame.free_texture(old_texture);
+				}
+				*preview_texture = Some(frame.alloc_texture(epi::Image::from_rgba_unmultiplied(
+					[enlarged.width() as usize, enlarged.height() as usize],
+					enlarged.as_raw(),
+				)));
+				*preview_image = Some(preview);
+				// The side panel was already drawn this frame, so make sure it gets another look.
+				ctx.request_repaint();
+			}
+		}
+
+		// dataset.json follows the class order and raster size, whatever changed them: adding, removing or reordering
+		// classes, a rescan, the command line or the negative class appearing.  A folder with no classes yet is left alone.
+		let metadata_now = (root_dir.clone(), gestures.clone(), (*width, *height));
+		if metadata_written.as_ref() != Some(&metadata_now) {
+			if !gestures.is_empty() || root_dir.join("dataset.json").exists() {
+				if let Err(e) = write_dataset_metadata(root_dir, gestures, (*width, *height)) {
+					*error_message = Some(format!("Failed to write dataset.json: {}", e));
+				}
+			}
+			*metadata_written = Some(metadata_now);
+		}
+
+		// Tab is a class shortcut, but egui also steps keyboard focus to the next widget on it.  A focused button would
+		// count as keyboard input and switch every shortcut off, so drop whatever focus Tab handed out.
+		if ctx.input().key_pressed(egui::Key::Tab) {
+			let focused = ctx.memory().focus();
+			if let Some(id) = focused {
+				ctx.memory().surrender_focus(id);
+			}
+		}
+	}
+}
+
+/// Redraw the app `delay` seconds from now, from a helper thread, since this egui has no `request_repaint_after`.
+/// `scheduled` holds the earliest wakeup already on its way, and a later one is folded into it.
+fn request_repaint_after(frame: &epi::Frame, scheduled: &Arc<Mutex<Option<Instant>>>, delay: f64) {
+	let due = Instant::now() + Duration::from_secs_f64(delay.max(0.0));
+	{
+		let mut next = scheduled.lock().unwrap();
+		if next.is_some_and(|next| next <= due) {
+			return;
+		}
+		*next = Some(due);
+	}
+	let signal = frame.lock().repaint_signal.clone();
+	let scheduled = scheduled.clone();
+	std::thread::spawn(move || {
+		std::thread::sleep(due.saturating_duration_since(Instant::now()));
+		let mut next = scheduled.lock().unwrap();
+		// An earlier wakeup may have replaced this one and already redrawn.  Leave whatever is due after it alone.
+		if *next == Some(due) {
+			*next = None;
+		}
+		drop(next);
+		signal.request_repaint();
+	});
+}
+
+/// Total length of every stroke in the drawing, in canvas units.
+fn total_stroke_length(drawing: &[Vec<StrokePoint>]) -> f32 {
+	drawing.iter().map(|line| line.windows(2).map(|w| w[0].pos.distance(w[1].pos)).sum::<f32>()).sum()
+}
+
+/// Drop points closer than a growing spacing to the last kept one until at most `max_points` remain.
+/// Each stroke keeps its endpoints, so nothing gets shorter.  Starts from `min_spacing`, in canvas units.
+fn thin_strokes(drawing: &mut [Vec<StrokePoint>], max_points: usize, min_spacing: f32) {
+	let mut spacing = min_spacing.max(0.001);
+	// Past a tenth of the canvas, thinning would visibly reshape the strokes.
+	while drawing.iter().map(|line| line.len()).sum::<usize>() > max_points && spacing <= 0.1 {
+		for line in drawing.iter_mut().filter(|line| line.len() > 2) {
+			let last = line[line.len() - 1];
+			let mut kept: Vec<StrokePoint> = vec![line[0]];
+			for p in &line[1..line.len() - 1] {
+				if kept[kept.len() - 1].pos.distance(p.pos) >= spacing {
+					kept.push(*p);
+				}
+			}
+			kept.push(last);
+			*line = kept;
+		}
+		spacing *= 2.0;
+	}
+}
+
+/// Append a copy of the last completed stroke, moved by `offset` canvas units.  Returns false if there's nothing to copy.
+fn duplicate_last_stroke(drawing: &mut Vec<Vec<StrokePoint>>, offset: egui::Vec2) -> bool {
+	let copy: Vec<StrokePoint> = match drawing.iter().rev().find(|line| !line.is_empty()) {
+		Some(line) => line.iter().map(|p| StrokePoint { pos: p.pos + offset, ..*p }).collect(),
+		None => return false,
+	};
+	// Keep the empty line for the next stroke at the end.
+	drawing.retain(|line| !line.is_empty());
+	drawing.push(copy);
+	drawing.push(vec![]);
+	true
+}
+
+/// Remove the last completed stroke and push it onto the redo stack.
+/// If the canvas is empty, restore whatever "Clear Painting" removed instead.
+fn undo_stroke(drawing: &mut Vec<Vec<StrokePoint>>, cleared_strokes: &mut Vec<Vec<StrokePoint>>, redo_stack: &mut Vec<Vec<StrokePoint>>) {
+	// The last line is the one currently being drawn.  We only undo completed strokes.
+	drawing.retain(|line| !line.is_empty());
+	if let Some(line) = drawing.pop() {
+		redo_stack.push(line);
+	} else {
+		drawing.append(cleared_strokes);
+	}
+	drawing.push(vec![]);
+}
+
+/// Put the most recently undone stroke back on the canvas.
+fn redo_stroke(drawing: &mut Vec<Vec<StrokePoint>>, redo_stack: &mut Vec<Vec<StrokePoint>>) {
+	if let Some(line) = redo_stack.pop() {
+		drawing.retain(|line| !line.is_empty());
+		drawing.push(line);
+		drawing.push(vec![]);
+	}
+}
+
+/// Canvas units per second at which a segment is drawn fully red.  Anything slower blends toward blue.
+const FAST_STROKE_SPEED: f32 = 3.0;
+
+/// `text` laid out in the heading font and scaled up to fill as much of `rect` as it can, centered.
+/// egui only draws text at its configured font sizes, so the glyphs are stretched as a mesh.  They blur, which is fine for a guide.
+fn scaled_text_mesh(ctx: &egui::CtxRef, text: &str, rect: egui::Rect, color: egui::Color32) -> egui::epaint::Mesh {
+	let galley = ctx.fonts().layout_no_wrap(text.to_owned(), egui::TextStyle::Heading, color);
+	let [tex_width, tex_height] = ctx.fonts().font_image().size();
+	let size = galley.size();
+	let scale = (rect.width() / size.x.max(1.0)).min(rect.height() / size.y.max(1.0));
+	let origin = rect.center() - size * scale / 2.0;
+	let mut mesh = egui::epaint::Mesh::default();
+	for row in &galley.rows {
+		let mut row_mesh = row.visuals.mesh.clone();
+		for vertex in row_mesh.vertices.iter_mut() {
+			vertex.pos = origin + vertex.pos.to_vec2() * scale;
+			// Glyph UVs are in texels until tessellation normally divides them by the font atlas size.
+			vertex.uv = egui::pos2(vertex.uv.x / tex_width as f32, vertex.uv.y / tex_height as f32);
+		}
+		mesh.append(row_mesh);
+	}
+	mesh
+}
+
+/// Arrowheads `spacing` screen points apart along a stroke, each `size` long and pointing the way the stroke travels.
+/// The first sits half a spacing in, or halfway along strokes shorter than that, so every stroke gets at least one.
+fn direction_arrows(points: &[egui::Pos2], spacing: f32, size: f32, stroke: egui::Stroke) -> Vec<egui::Shape> {
+	let total: f32 = points.windows(2).map(|w| w[0].distance(w[1])).sum();
+	let mut next = (spacing / 2.0).min(total / 2.0);
+	let mut travelled = 0.0;
+	let mut shapes = vec![];
+	for w in points.windows(2) {
+		let length = w[0].distance(w[1]);
+		if length <= 0.0 { continue; }
+		let dir = (w[1] - w[0]) / length;
+		while next <= travelled + length {
+			let tip = w[0] + dir * (next - travelled);
+			let back = tip - dir * size;
+			let side = dir.rot90() * size * 0.5;
+			shapes.push(egui::Shape::line_segment([back + side, tip], stroke));
+			shapes.push(egui::Shape::line_segment([back - side, tip], stroke));
+			next += spacing;
+		}
+		travelled += length;
+	}
+	shapes
+}
+
+/// Colors for numbered strokes, picked to stay distinct from their neighbours on light and dark canvases.
+const STROKE_PALETTE: [egui::Color32; 8] = [
+	egui::Color32::from_rgb(230, 25, 75),
+	egui::Color32::from_rgb(60, 180, 75),
+	egui::Color32::from_rgb(0, 130, 200),
+	egui::Color32::from_rgb(245, 130, 48),
+	egui::Color32::from_rgb(145, 30, 180),
+	egui::Color32::from_rgb(70, 200, 200),
+	egui::Color32::from_rgb(240, 50, 230),
+	egui::Color32::from_rgb(170, 110, 40),
+];
+
+/// Color for the segment between two points, by how quickly it was drawn.
+fn speed_color(a: StrokePoint, b: StrokePoint) -> egui::Color32 {
+	let dt = (b.t - a.t) / 1000.0;
+	// Points captured in the same instant have no measurable speed.  Treat them as fast rather than dividing by zero.
+	let speed = if dt > 0.0 { a.pos.distance(b.pos) / dt } else { FAST_STROKE_SPEED };
+	let t = (speed / FAST_STROKE_SPEED).clamp(0.0, 1.0);
+	egui::Color32::from_rgb((255.0 * t) as u8, 64, (255.0 * (1.0 - t)) as u8)
+}
+
+/// Index of the stroke passing closest to `pos`, if any comes within `radius` of it.
+fn stroke_near(drawing: &[Vec<StrokePoint>], pos: egui::Pos2, radius: f32) -> Option<usize> {
+	drawing.iter().enumerate()
+		.filter_map(|(idx, line)| {
+			let distance = match line.len() {
+				0 => return None,
+				1 => line[0].pos.distance(pos),
+				_ => line.windows(2).map(|w| point_segment_distance(pos, w[0].pos, w[1].pos)).fold(f32::INFINITY, f32::min),
+			};
+			(distance <= radius).then_some((idx, distance))
+		})
+		.min_by(|a, b| a.1.total_cmp(&b.1))
+		.map(|(idx, _)| idx)
+}
+
+/// Remove every point within `radius` of `center`, splitting any line that loses points from its middle.
+/// Returns true if anything was erased.
+fn erase_near(drawing: &mut Vec<Vec<StrokePoint>>, center: egui::Pos2, radius: f32) -> bool {
+	let mut erased = false;
+	let mut remaining = Vec::with_capacity(drawing.len());
+	for line in drawing.drain(..) {
+		let mut piece = vec![];
+		for p in line {
+			if p.pos.distance(center) <= radius {
+				erased = true;
+				if !piece.is_empty() {
+					remaining.push(std::mem::take(&mut piece));
+				}
+			} else {
+				piece.push(p);
+			}
+		}
+		if !piece.is_empty() {
+			remaining.push(piece);
+		}
+	}
+	*drawing = remaining;
+	// Keep an empty line at the end for the next stroke to go into.
+	drawing.push(vec![]);
+	erased
+}
+
+/// The pressure of the latest pen or touch event this frame, or 1 if there was none.
+/// Platforms without pressure sensing report a force of 0, which is treated as no reading.
+/// Only the drawing finger's touches count, so a resting palm can't change it.
+fn pointer_pressure(ctx: &egui::CtxRef, primary_touch: Option<egui::TouchId>) -> f32 {
+	ctx.input().events.iter().rev()
+		.find_map(|event| match event {
+			egui::Event::Touch { id, force, .. } if *force > 0.0 && primary_touch.is_none_or(|primary| primary == *id) => Some(force.min(1.0)),
+			_ => None,
+		})
+		.unwrap_or(1.0)
+}
+
+/// A few random strokes of random line segments, standing in for "no gesture" in the negative class.
+fn random_scribble(rng: &mut impl Rng) -> Vec<Vec<StrokePoint>> {
+	(0..rng.gen_range(1..=3)).map(|_| {
+		(0..rng.gen_range(2..=5)).map(|i| StrokePoint {
+			pos: egui::pos2(rng.gen(), rng.gen()),
+			t: i as f32 * 50.0,
+			pressure: 1.0,
+		}).collect()
+	}).collect()
+}
+
+/// Trim and lowercase a proposed class name, refusing anything that wouldn't make a single, plain directory name.
+fn clean_class_name(name: &str) -> Result<String, String> {
+	let name = name.trim().to_ascii_lowercase();
+	check_class_dir_name(&name)?;
+	// Rescans skip the split and export folders, so a class by one of their names would vanish on the next launch.
+	if SPLIT_NAMES.contains(&name.as_str()) {
+		return Err(format!("'{}' is reserved for \"Split dataset\".", name));
+	}
+	if name == IMAGE_FOLDER_NAME {
+		return Err(format!("'{}' is reserved for \"Export ImageFolder\".", name));
+	}
+	if name == NEGATIVE_CLASS {
+		return Err("That class is filled by \"Save as Negative\".".to_owned());
+	}
+	Ok(name)
+}
+
+/// The part of `clean_class_name` that keeps a name to one folder directly under the root.  Every path built from a class name
+/// goes through this.  It doesn't insist on lower case, so class folders made by hand with capitals still work.
+fn check_class_dir_name(name: &str) -> Result<(), String> {
+	if name.is_empty() {
+		return Err("Class names can't be empty.".to_owned());
+	}
+	if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+		return Err("Class names may only contain letters, numbers, '_' and '-'.".to_owned());
+	}
+	Ok(())
+}
+
+/// A class's folder in the root, or an error if `class_name` would put it anywhere else.
+fn class_dir(root_dir: &Path, class_name: &str) -> Result<PathBuf, SaveError> {
+	check_class_dir_name(class_name)
+		.map_err(|e| SaveError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("Can't save to class '{}': {}", class_name, e))))?;
+	Ok(root_dir.join(class_name))
+}
+
+/// Remove a class folder and everything in it.  Refuses any name that could reach outside a single folder in the root,
+/// since `root_dir.join("")` is the root itself.
+fn delete_class_dir(root_dir: &Path, class_name: &str) -> std::io::Result<()> {
+	let dir = root_dir.join(class_name);
+	// Only the plain folder-name check, so classes rescanned from mixed-case folders can still be deleted.
+	if check_class_dir_name(class_name).is_err() || dir.parent() != Some(root_dir) {
+		return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("'{}' isn't a class folder that can be deleted", class_name)));
+	}
+	std::fs::remove_dir_all(dir)
+}
+
+/// The sample number a file belongs to, if its name starts with one.
+/// Sidecars like "12.json" and augmented copies like "12_fliph.png" share the number of their sample.
+fn leading_number(file_name: &str) -> Option<u32> {
+	let digits: String = file_name.chars().take_while(|c| c.is_ascii_digit()).collect();
+	digits.parse().ok()
+}
+
+/// Seconds since the Unix epoch, or 0 if the clock is set before it.
+fn unix_timestamp() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// A piece of a sample filename pattern such as "{class}_{n:05}".
+enum NameToken {
+	Literal(String),
+	Class,
+	// The sample number, zero padded to the given width.
+	Number(usize),
+	// Seconds since the Unix epoch when the sample was saved.
+	Timestamp,
+}
+
+/// Why a filename pattern can't be used, if it can't.  Its text ends up in file names, so it mustn't lead out of the class folder.
+fn filename_pattern_error(pattern: &str) -> Option<&'static str> {
+	if pattern.contains('/') || pattern.contains('\\') || pattern.contains("..") {
+		Some("Patterns can't contain '/', '\\' or '..'.")
+	} else {
+		None
+	}
+}
+
+/// Split a filename pattern into its tokens.  Unknown `{...}` tokens are kept as literal text.
+/// A pattern without a sample number would have every sample overwrite the last, so `_{n}` is appended to those.
+/// A pattern that `filename_pattern_error` refuses is read as plain `{n}`.
+fn parse_filename_pattern(pattern: &str) -> Vec<NameToken> {
+	if filename_pattern_error(pattern).is_some() {
+		return vec![NameToken::Number(0)];
+	}
+	let mut tokens = vec![];
+	let mut literal = String::new();
+	let mut rest = pattern;
+	while let Some(open) = rest.find('{') {
+		literal.push_str(&rest[..open]);
+		rest = &rest[open..];
+		let close = match rest.find('}') {
+			Some(close) => close,
+			None => break,
+		};
+		let token = match &rest[1..close] {
+			"class" => Some(NameToken::Class),
+			"n" => Some(NameToken::Number(0)),
+			"timestamp" => Some(NameToken::Timestamp),
+			other => other.strip_prefix("n:").and_then(|width| width.parse().ok()).map(NameToken::Number),
+		};
+		match token {
+			Some(token) => {
+				if !literal.is_empty() {
+					tokens.push(NameToken::Literal(std::mem::take(&mut literal)));
+				}
+				tokens.push(token);
+			}
+			None => literal.push_str(&rest[..=close]),
+		}
+		rest = &rest[close+1..];
+	}
+	literal.push_str(rest);
+	if !literal.is_empty() {
+		tokens.push(NameToken::Literal(literal));
+	}
+	if !tokens.iter().any(|t| matches!(t, NameToken::Number(_))) {
+		tokens.push(NameToken::Literal("_".to_owned()));
+		tokens.push(NameToken::Number(0));
+	}
+	tokens
+}
+
+/// Fill in a filename pattern for one sample.  The result has no extension.
+fn format_sample_name(pattern: &str, class_name: &str, sample_number: u32, timestamp: u64) -> String {
+	parse_filename_pattern(pattern).iter().map(|token| match token {
+		NameToken::Literal(text) => text.clone(),
+		NameToken::Class => class_name.to_owned(),
+		NameToken::Number(width) => format!("{:0width$}", sample_number, width = *width),
+		NameToken::Timestamp => timestamp.to_string(),
+	}).collect()
+}
+
+/// The sample number a file belongs to, if its name was produced by `pattern`.
+/// Anything after the pattern is ignored, so sidecars and augmented copies like "12_fliph.png" match their sample.
+fn sample_number_from_name(pattern: &str, class_name: &str, file_name: &str) -> Option<u32> {
+	let mut rest = file_name;
+	let mut number = None;
+	for token in parse_filename_pattern(pattern) {
+		match token {
+			NameToken::Literal(text) => rest = rest.strip_prefix(text.as_str())?,
+			NameToken::Class => rest = rest.strip_prefix(class_name)?,
+			NameToken::Number(_) | NameToken::Timestamp => {
+				let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+				if digits == 0 {
+					return None;
+				}
+				if matches!(token, NameToken::Number(_)) {
+					number = rest[..digits].parse().ok();
+				}
+				rest = &rest[digits..];
+			}
+		}
+	}
+	number
+}
+
+/// The name of the shard subfolder a sample goes in, e.g. "003" for sample 3141 with 1000 samples per shard.
+fn shard_name(sample_number: u32, shard_size: u32) -> String {
+	format!("{:03}", sample_number / shard_size.max(1))
+}
+
+/// Every file in a class directory, including those inside shard subfolders.
+/// Shards are recognized by their all-digit names, so they're found whatever the current shard setting is.
+fn class_files(class_dir: &Path) -> Vec<PathBuf> {
+	let entries = match std::fs::read_dir(class_dir) {
+		Ok(entries) => entries,
+		Err(_) => return vec![],
+	};
+	let mut files = vec![];
+	for path in entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()) {
+		let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+		if path.is_dir() && !name.is_empty() && name.chars().all(|c| c.is_ascii_digit()) {
+			if let Ok(shard) = std::fs::read_dir(&path) {
+				files.extend(shard.filter_map(|entry| entry.ok()).map(|entry| entry.path()).filter(|p| p.is_file()));
+			}
+		} else if path.is_file() {
+			files.push(path);
+		}
+	}
+	files
+}
+
+/// The sample numbers of every file in a class directory that was named with `pattern`.
+fn class_sample_numbers(class_dir: &Path, pattern: &str) -> Vec<u32> {
+	let class_name = class_dir.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+	class_files(class_dir)
+		.iter()
+		.filter_map(|path| path.file_name())
+		.filter_map(|name| sample_number_from_name(pattern, &class_name, &name.to_string_lossy()))
+		.collect()
+}
+
+/// How many distinct samples a class directory holds.  Augmented copies and sidecars count with their original.
+fn count_samples(class_dir: &Path, pattern: &str) -> usize {
+	let numbers: std::collections::HashSet<u32> = class_sample_numbers(class_dir, pattern).into_iter().collect();
+	numbers.len()
+}
+
+/// One past the highest numbered sample already in a class's directory, or 0 if there are none.
+/// Files that don't match the filename pattern are ignored, and gaps in the numbering are left alone.
+fn next_sample_number(class_dir: &Path, pattern: &str) -> u32 {
+	class_sample_numbers(class_dir, pattern).into_iter().max().map_or(0, |n| n + 1)
+}
+
+/// The number the next sample of a class should be saved under.
+/// Starts from the class's counter but skips any number that already has files, so a stale counter can't overwrite a sample.
+fn claim_sample_number(sample_counts: &mut HashMap<String, u32>, root_dir: &Path, class_name: &str, pattern: &str) -> Result<u32, SaveError> {
+	let class_dir = class_dir(root_dir, class_name)?;
+	let counter = *sample_counts.entry(class_name.to_owned()).or_insert_with(|| next_sample_number(&class_dir, pattern));
+	let taken: std::collections::HashSet<u32> = class_sample_numbers(&class_dir, pattern).into_iter().collect();
+	Ok((counter..).find(|n| !taken.contains(n)).unwrap_or(counter))
+}
+
+/// A snapshot of how big the dataset is, for the stats window.
+pub struct DatasetStats {
+	per_class: Vec<(String, usize)>,
+	// Every file in the class folders, sidecars and augments included.
+	disk_bytes: u64,
+}
+
+fn compute_dataset_stats(root_dir: &Path, gestures: &[String], pattern: &str) -> DatasetStats {
+	let mut disk_bytes = 0;
+	let mut per_class = vec![];
+	for class_name in gestures {
+		let class_dir = root_dir.join(class_name);
+		per_class.push((class_name.clone(), count_samples(&class_dir, pattern)));
+		disk_bytes += class_files(&class_dir)
+			.iter()
+			.filter_map(|path| path.metadata().ok())
+			.map(|meta| meta.len())
+			.sum::<u64>();
+	}
+	DatasetStats { per_class, disk_bytes }
+}
+
+/// A saved image as grayscale.  For images with transparency the alpha is the stroke coverage, so that becomes the intensity.
+fn stroke_intensity(img: &image::DynamicImage) -> image::GrayImage {
+	if img.color().has_alpha() {
+		let with_alpha = img.to_luma_alpha8();
+		image::GrayImage::from_fn(with_alpha.width(), with_alpha.height(), |x, y| image::Luma([with_alpha.get_pixel(x, y).0[1]]))
+	} else {
+		img.to_luma8()
+	}
+}
+
+/// A 64 bit perceptual hash of an image: which cells of an 8x8 grayscale thumbnail are brighter than average.
+/// Similar drawings differ in only a few bits, which `count_ones` on the XOR of two hashes measures.
+fn average_hash(img: &image::DynamicImage) -> u64 {
+	let thumb = image::imageops::resize(&stroke_intensity(img), 8, 8, image::imageops::FilterType::Triangle);
+	let mean = thumb.pixels().map(|p| p.0[0] as u32).sum::<u32>() / 64;
+	thumb.pixels().enumerate().fold(0, |hash, (i, p)| if p.0[0] as u32 > mean { hash | 1 << i } else { hash })
+}
+
+/// A byte count in the largest unit that keeps it at or above one, e.g. "3.2 MB".
+fn format_bytes(bytes: u64) -> String {
+	let units = ["B", "KB", "MB", "GB", "TB"];
+	let mut value = bytes as f64;
+	let mut unit = 0;
+	while value >= 1024.0 && unit < units.len() - 1 {
+		value /= 1024.0;
+		unit += 1;
+	}
+	if unit == 0 { format!("{} B", bytes) } else { format!("{:.1} {}", value, units[unit]) }
+}
+
+/// Create `dir` if needed and check that files can actually be written in it, by creating and removing a scratch file.
+/// Catches read-only folders and paths that already exist as files before any samples are lost to them.
+fn ensure_writable_dir(dir: &Path) -> std::io::Result<()> {
+	std::fs::create_dir_all(dir)?;
+	// Named for this process, so a probe left behind by a crash or another copy of the app can't get in the way.
+	let probe = dir.join(format!(".write_test_{}", std::process::id()));
+	std::fs::OpenOptions::new().write(true).create(true).truncate(true).open(&probe)?;
+	std::fs::remove_file(&probe)
+}
+
+/// Written into the open dataset folder so a second copy of the app pointed at it can warn.
+const LOCK_FILE_NAME: &str = ".gesture_dataset.lock";
+
+/// Move our lock from the folder in `locked_dir` to `root_dir`.  An empty `root_dir` only releases the old one.
+/// Returns a warning if another session's lock was already there.  It's taken over regardless, since a crashed run leaves its lock behind.
+fn claim_dataset_dir(locked_dir: &mut Option<PathBuf>, root_dir: &Path) -> Option<String> {
+	let ours = format!("pid={}", std::process::id());
+	if let Some(old) = locked_dir.take() {
+		let lock = old.join(LOCK_FILE_NAME);
+		// Only remove the lock if it's still ours.  Another session may have taken the folder over since.
+		if std::fs::read_to_string(&lock).is_ok_and(|contents| contents.lines().next() == Some(ours.as_str())) {
+			let _ = std::fs::remove_file(&lock);
+		}
+	}
+	if root_dir.as_os_str().is_empty() {
+		return None;
+	}
+
+	let lock = root_dir.join(LOCK_FILE_NAME);
+	let warning = std::fs::read_to_string(&lock).ok().map(|contents| {
+		let pid = contents.lines().find_map(|line| line.strip_prefix("pid=")).unwrap_or("?");
+		let started = contents.lines().find_map(|line| line.strip_prefix("started=")?.parse().ok()).map(format_time_of_day).unwrap_or_else(|| "?".to_owned());
+		format!(
+			"Another session (pid {}, opened at {} UTC) may be using this dataset folder.  Two copies saving here will overwrite each other's samples.  If that copy has closed or crashed, ignore this.",
+			pid, started
+		)
+	});
+	match std::fs::write(&lock, format!("{}\nstarted={}\n", ours, unix_timestamp())) {
+		Ok(()) => *locked_dir = Some(root_dir.to_path_buf()),
+		Err(e) => return Some(format!("Failed to write {}: {}", lock.display(), e)),
+	}
+	warning
+}
+
+/// Names of the directories under `root` that look like gesture classes.
+/// Hidden directories are skipped, and so is anything holding files but no numbered samples, like `src` or `target`.
+fn find_gesture_dirs(root: &Path, pattern: &str) -> Vec<String> {
+	let entries = match std::fs::read_dir(root) {
+		Ok(entries) => entries,
+		Err(_) => return vec![],
+	};
+	let mut found: Vec<String> = entries
+		.filter_map(|entry| entry.ok())
+		.map(|entry| entry.path())
+		.filter(|path| path.is_dir())
+		.filter(|path| {
+			let is_empty = std::fs::read_dir(path).is_ok_and(|mut e| e.next().is_none());
+			is_empty || next_sample_number(path, pattern) > 0
+		})
+		.filter_map(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()))
+		.filter(|name| !name.starts_with('.'))
+		// Split and export folders hold class folders of their own, which can pass for shards when classes are digits.
+		.filter(|name| !SPLIT_NAMES.contains(&name.as_str()) && name != IMAGE_FOLDER_NAME)
+		.collect();
+	found.sort();
+	found
+}
+
+/// Add any class directories found on disk to the gesture list and bring every counter up to date with the files present.
+/// Each class resumes after its highest numbered sample so nothing gets clobbered.
+fn rescan_gestures(root_dir: &Path, pattern: &str, gestures: &mut Vec<String>, sample_counts: &mut HashMap<String, u32>, saved_counts: &mut HashMap<String, usize>) {
+	for name in find_gesture_dirs(root_dir, pattern) {
+		if !gestures.contains(&name) {
+			gestures.push(name);
+		}
+	}
+	for g in gestures.iter() {
+		let counter = sample_counts.entry(g.clone()).or_insert(0);
+		*counter = (*counter).max(next_sample_number(&root_dir.join(g), pattern));
+		saved_counts.insert(g.clone(), count_samples(&root_dir.join(g), pattern));
+	}
+}
+
+/// Identifies one sample on disk.  Every file written for it (image and sidecars) shares this location.
+pub struct SampleLocation {
+	root_dir: PathBuf,
+	class_name: String,
+	sample_number: u32,
+	// The shard subfolder of the class directory the files go in, if sharding is on.
+	shard: Option<String>,
+	// The filename pattern filled in for this sample, without an extension.
+	file_stem: String,
+	// Appended to the file stem for augmented copies, e.g. "_fliph".  Empty for the original.
+	suffix: String,
+}
+
+impl SampleLocation {
+	/// Name the sample using `pattern`.  The timestamp is taken once here, so every file for the sample agrees on it.
+	fn new(root_dir: &Path, class_name: &str, sample_number: u32, pattern: &str, shard_size: Option<u32>) -> Result<SampleLocation, SaveError> {
+		class_dir(root_dir, class_name)?;
+		let timestamp = unix_timestamp();
+		Ok(SampleLocation {
+			root_dir: root_dir.to_path_buf(),
+			class_name: class_name.to_owned(),
+			sample_number,
+			shard: shard_size.map(|size| shard_name(sample_number, size)),
+			file_stem: format_sample_name(pattern, class_name, sample_number, timestamp),
+			suffix: String::new(),
+		})
+	}
+
+	/// Where this sample is written with the given file extension.
+	/// The class directory may have been removed out from under us, so it's recreated if needed.
+	/// Existing files are never overwritten.  Finding one is an error.
+	fn path(&self, extension: &str) -> Result<PathBuf, std::io::Error> {
+		let path = self.dir()?.join(format!("{}{}.{}", self.file_stem, self.suffix, extension));
+		if path.exists() {
+			return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} already exists", path.display())));
+		}
+		Ok(path)
+	}
+
+	/// The class directory, or the sample's shard of it, created if it doesn't exist.
+	fn dir(&self) -> Result<PathBuf, std::io::Error> {
+		let mut dir = self.root_dir.join(&self.class_name);
+		if let Some(shard) = &self.shard {
+			dir.push(shard);
+		}
+		std::fs::create_dir_all(&dir)?;
+		Ok(dir)
+	}
+
+	/// The same sample, saved under a different suffix.
+	fn with_suffix(&self, suffix: &str) -> SampleLocation {
+		SampleLocation {
+			root_dir: self.root_dir.clone(),
+			class_name: self.class_name.clone(),
+			sample_number: self.sample_number,
+			shard: self.shard.clone(),
+			file_stem: self.file_stem.clone(),
+			suffix: suffix.to_owned(),
+		}
+	}
+}
+
+/// Rename every file of a saved sample so it belongs to `to` instead, keeping each file's suffix and extension.
+/// JSON sidecars have their class and sample number rewritten to match.  Returns the new paths.
+fn move_sample(sample: &SavedSample, to: &SampleLocation) -> Result<Vec<PathBuf>, SaveError> {
+	let dir = to.dir()?;
+	let targets: Vec<PathBuf> = sample.files.iter().map(|file| {
+		let name = file.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+		let rest = name.strip_prefix(sample.location.file_stem.as_str()).unwrap_or(&name);
+		dir.join(format!("{}{}", to.file_stem, rest))
+	}).collect();
+	// Check everything up front so a clash doesn't leave the sample split between two classes.
+	if let Some(existing) = targets.iter().find(|target| target.exists()) {
+		return Err(SaveError::Io(std::io::Error::new(std::io::ErrorKind::AlreadyExists, format!("{} already exists", existing.display()))));
+	}
+
+	for (file, target) in sample.files.iter().zip(targets.iter()) {
+		std::fs::rename(file, target)?;
+		if target.extension().is_some_and(|ext| ext == "json") {
+			let mut doc: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(target)?)
+				.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+			doc["class"] = serde_json::json!(to.class_name);
+			doc["sample"] = serde_json::json!(to.sample_number);
+			std::fs::write(target, doc.to_string())?;
+		}
+	}
+	Ok(targets)
+}
+
+fn save_image(lines: &[Vec<egui::Pos2>], pressures: &[Vec<f32>], location: &SampleLocation, raster_size: (u32, u32), options: &RasterOptions, background: Option<&image::RgbImage>) -> Result<PathBuf, SaveError> {
+	let mut img = rasterize(lines, pressures, raster_size, options);
+	if let Some(background) = background {
+		img = composite_background(&img, background, options);
+	}
+	add_noise(&mut img, options, &mut rand::thread_rng());
+
+	// Save the example.
+	let path = location.path(options.output_format.extension())?;
+	img.save_with_format(&path, options.output_format.image_format())?;
+	Ok(path)
+}
+
+/// The mirrored copies a sample can be saved with: whether each flips horizontally and vertically, and its file name suffix.
+const FLIP_AUGMENTS: [(bool, bool, &str); 3] = [(true, false, "_fliph"), (false, true, "_flipv"), (true, true, "_fliphv")];
+
+/// Save the image for a sample, plus any augmented copies and sidecar files enabled in `export`, its canvas bounds, and its tags if it has any.
+/// Returns every file written, starting with the main image.
+fn save_sample(drawing: &[Vec<StrokePoint>], location: &SampleLocation, raster_size: (u32, u32), options: &RasterOptions, export: &ExportOptions, background: Option<&image::RgbImage>, tags: &[String]) -> Result<Vec<PathBuf>, SaveError> {
+	let lines = stroke_positions(drawing);
+	// Augments move points around but keep their order, so the same pressures apply.
+	let pressures = stroke_pressures(drawing);
+	let mut files = vec![save_image(&lines, &pressures, location, raster_size, options, background)?];
+
+	// Mirrored copies share the sample number, so they stay easy to match up with their original.
+	let mut variants = vec![];
+	for (flip_h, flip_v, suffix) in FLIP_AUGMENTS {
+		if (flip_h && !export.augment_flip_h) || (flip_v && !export.augment_flip_v) { continue; }
+		variants.push((flip_strokes(&lines, flip_h, flip_v), location.with_suffix(suffix)));
+	}
+	let mut rng = rand::thread_rng();
+	for i in 0..export.rotation_augments {
+		let angle = rng.gen_range(-export.max_rotation_deg..=export.max_rotation_deg).to_radians();
+		variants.push((rotate_strokes(&lines, angle), location.with_suffix(&format!("_rot{}", i))));
+	}
+	for (variant_lines, variant) in &variants {
+		files.push(save_image(variant_lines, &pressures, variant, raster_size, options, background)?);
+	}
+	files.push(save_bounds(&lines, location)?);
+
+	// Everything from here on is stroke data rather than images, so it gets the simplified strokes.
+	let simplified: Vec<Vec<StrokePoint>> = drawing.iter()
+		.map(|line| simplify_indices(&line.iter().map(|p| p.pos).collect::<Vec<_>>(), export.simplify_epsilon).iter().map(|&i| line[i]).collect())
+		.collect();
+	let drawing = &simplified[..];
+	if export.export_json {
+		let strokes: Vec<Vec<StrokePoint>> = drawing.iter().filter(|line| !line.is_empty()).cloned().collect();
+		let in_order: Vec<usize> = (0..strokes.len()).collect();
+		files.push(save_strokes(&strokes, &in_order, location, raster_size, options)?);
+		for (i, order) in stroke_permutations(strokes.len(), export.stroke_order_shuffles, &mut rng).iter().enumerate() {
+			let shuffled: Vec<Vec<StrokePoint>> = order.iter().map(|&idx| strokes[idx].clone()).collect();
+			files.push(save_strokes(&shuffled, order, &location.with_suffix(&format!("_order{}", i)), raster_size, options)?);
+		}
+	}
+	if export.export_time_series {
+		files.push(save_time_series(drawing, location, raster_size, options)?);
+	}
+	if export.export_svg {
+		files.push(save_svg(drawing, location, raster_size, options)?);
+	}
+	if export.export_resampled {
+		files.push(save_resampled_strokes(drawing, location, raster_size, options, export.resample_count)?);
+	}
+	// Augmented copies get their own tags file, so every manifest row finds its tags next to its image.
+	if !tags.is_empty() {
+		files.push(save_tags(tags, location)?);
+		for (_, variant) in &variants {
+			files.push(save_tags(tags, variant)?);
+		}
+	}
+	Ok(files)
+}
+
+/// The non-empty, trimmed entries of a comma-separated tag list.
+fn parse_tags(text: &str) -> Vec<String> {
+	text.split(',').map(str::trim).filter(|tag| !tag.is_empty()).map(str::to_owned).collect()
+}
+
+/// Write a sample's free-form tags next to its image, for slicing the dataset later.
+fn save_tags(tags: &[String], location: &SampleLocation) -> Result<PathBuf, SaveError> {
+	let doc = serde_json::json!({
+		"class": location.class_name,
+		"sample": location.sample_number,
+		"tags": tags,
+	});
+	let path = location.path("tags.json")?;
+	std::fs::write(&path, doc.to_string())?;
+	Ok(path)
+}
+
+/// Up to `count` distinct orderings of `stroke_count` strokes, none of them the original order.
+/// Fewer come back when there aren't that many, e.g. two strokes only have one other order.
+fn stroke_permutations(stroke_count: usize, count: u32, rng: &mut impl Rng) -> Vec<Vec<usize>> {
+	let possible = (1..=stroke_count).try_fold(1usize, |total, n| total.checked_mul(n)).unwrap_or(usize::MAX) - 1;
+	let wanted = (count as usize).min(possible);
+	let in_order: Vec<usize> = (0..stroke_count).collect();
+	let mut found: Vec<Vec<usize>> = vec![];
+	while found.len() < wanted {
+		let mut order = in_order.clone();
+		order.shuffle(rng);
+		if order != in_order && !found.contains(&order) {
+			found.push(order);
+		}
+	}
+	found
+}
+
+/// Write the strokes as JSON next to the image so the point order isn't lost to rasterization.
+/// Coordinates are normalized exactly as they are for the raster, but kept in the 0/1 range.
+/// `order` gives the position each stroke had when it was drawn, and is saved as `stroke_order`.
+fn save_strokes(lines: &[Vec<StrokePoint>], order: &[usize], location: &SampleLocation, raster_size: (u32, u32), options: &RasterOptions) -> Result<PathBuf, SaveError> {
+	let (_, _, inner_width, inner_height) = padded_area(raster_size, options.padding);
+	let normalized = normalize_strokes(&stroke_positions(lines), inner_width / inner_height, options);
+	let strokes: Vec<serde_json::Value> = lines.iter().zip(normalized.iter())
+		.filter(|(line, _)| !line.is_empty())
+		.map(|(line, norm_line)| {
+			line.iter().zip(norm_line.iter()).map(|(p, pt)| serde_json::json!({"x": pt.x, "y": pt.y, "t": p.t, "pressure": p.pressure})).collect()
+		})
+		.collect();
+	let doc = serde_json::json!({
+		"class": location.class_name,
+		"sample": location.sample_number,
+		"width": raster_size.0,
+		"height": raster_size.1,
+		"canvas_bounds": canvas_bounds(&stroke_positions(lines)),
+		"stroke_order": order,
+		"strokes": strokes,
+	});
+
+	let path = location.path("json")?;
+	std::fs::write(&path, doc.to_string())?;
+	Ok(path)
+}
+
+/// Where the drawing sat on the canvas before normalization threw that away, for models that want position and scale.
+/// Null for an empty drawing, and the aspect is null for a perfectly flat one.
+fn canvas_bounds(lines: &[Vec<egui::Pos2>]) -> serde_json::Value {
+	let (mut min, mut max) = (egui::pos2(f32::MAX, f32::MAX), egui::pos2(f32::MIN, f32::MIN));
+	for p in lines.iter().flatten() {
+		min = min.min(*p);
+		max = max.max(*p);
+	}
+	if min.x > max.x {
+		return serde_json::Value::Null;
+	}
+	let aspect = if max.y > min.y { serde_json::json!((max.x - min.x) / (max.y - min.y)) } else { serde_json::Value::Null };
+	serde_json::json!({"min_x": min.x, "min_y": min.y, "max_x": max.x, "max_y": max.y, "aspect": aspect})
+}
+
+/// Write a sample's canvas bounds next to its image.  Written for every sample, so the manifest has them even without stroke JSON.
+fn save_bounds(lines: &[Vec<egui::Pos2>], location: &SampleLocation) -> Result<PathBuf, SaveError> {
+	let doc = serde_json::json!({
+		"class": location.class_name,
+		"sample": location.sample_number,
+		"canvas_bounds": canvas_bounds(lines),
+	});
+	let path = location.path("bounds.json")?;
+	std::fs::write(&path, doc.to_string())?;
+	Ok(path)
+}
+
+/// Write a CSV with one row per captured point, including timing and velocity, for online/sequence classifiers.
+/// Positions are normalized like the raster.  Velocity is in normalized units per second, and zero for the first point of each stroke.
+fn save_time_series(lines: &[Vec<StrokePoint>], location: &SampleLocation, raster_size: (u32, u32), options: &RasterOptions) -> Result<PathBuf, SaveError> {
+	let (_, _, inner_width, inner_height) = padded_area(raster_size, options.padding);
+	let normalized = normalize_strokes(&stroke_positions(lines), inner_width / inner_height, options);
+
+	let mut csv = String::from("stroke,point,x,y,t_ms,vx,vy,speed,pressure\n");
+	for (stroke_idx, (line, norm_line)) in lines.iter().zip(normalized.iter()).filter(|(line, _)| !line.is_empty()).enumerate() {
+		for (point_idx, (p, pt)) in line.iter().zip(norm_line.iter()).enumerate() {
+			let (mut vx, mut vy) = (0.0, 0.0);
+			if point_idx > 0 {
+				let dt = (p.t - line[point_idx-1].t) / 1000.0;
+				if dt > 0.0 {
+					vx = (pt.x - norm_line[point_idx-1].x) / dt;
+					vy = (pt.y - norm_line[point_idx-1].y) / dt;
+				}
+			}
+			let speed = (vx*vx + vy*vy).sqrt();
+			csv.push_str(&format!("{},{},{},{},{},{},{},{},{}\n", stroke_idx, point_idx, pt.x, pt.y, p.t, vx, vy, speed, p.pressure));
+		}
+	}
+
+	let path = location.path("csv")?;
+	std::fs::write(&path, csv)?;
+	Ok(path)
+}
+
+/// Write the strokes as an SVG with one path per stroke, smoothed and laid out exactly as they are in the raster.
+/// Taps become zero length paths, which the round caps draw as dots.
+fn save_svg(lines: &[Vec<StrokePoint>], location: &SampleLocation, raster_size: (u32, u32), options: &RasterOptions) -> Result<PathBuf, SaveError> {
+	let (pad_x, pad_y, inner_width, inner_height) = padded_area(raster_size, options.padding);
+	let smoothed: Vec<Vec<egui::Pos2>> = stroke_positions(lines).iter().map(|line| chaikin_smooth(line, options.smoothing_iterations)).collect();
+	let normalized = normalize_strokes(&smoothed, inner_width / inner_height, options);
+	let (background, foreground) = if options.invert_colors { ("white", "black") } else { ("black", "white") };
+
+	let mut svg = format!(
+		"<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">\n<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n",
+		background, w = raster_size.0, h = raster_size.1,
+	);
+	for line in normalized.iter().filter(|line| !line.is_empty()) {
+		let points: Vec<String> = line.iter().map(|pt| format!("{:.3} {:.3}", pad_x + pt.x*inner_width, pad_y + pt.y*inner_height)).collect();
+		let data = if points.len() == 1 { format!("M {} l 0 0", points[0]) } else { format!("M {}", points.join(" L ")) };
+		svg.push_str(&format!(
+			"<path d=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\" stroke-linecap=\"round\" stroke-linejoin=\"round\"/>\n",
+			data, foreground, options.line_thickness,
+		));
+	}
+	svg.push_str("</svg>\n");
+
+	let path = location.path("svg")?;
+	std::fs::write(&path, svg)?;
+	Ok(path)
+}
+
+/// Write every stroke resampled to exactly `points_per_stroke` equidistant points, normalized like the raster.
+fn save_resampled_strokes(lines: &[Vec<StrokePoint>], location: &SampleLocation, raster_size: (u32, u32), options: &RasterOptions, points_per_stroke: usize) -> Result<PathBuf, SaveError> {
+	let (_, _, inner_width, inner_height) = padded_area(raster_size, options.padding);
+	let normalized = normalize_strokes(&stroke_positions(lines), inner_width / inner_height, options);
+	let strokes: Vec<serde_json::Value> = normalized.iter()
+		.filter(|line| !line.is_empty())
+		.map(|line| resample_stroke(line, points_per_stroke).iter().map(|pt| serde_json::json!({"x": pt.x, "y": pt.y})).collect())
+		.collect();
+	let doc = serde_json::json!({
+		"class": location.class_name,
+		"sample": location.sample_number,
+		"points_per_stroke": points_per_stroke,
+		"strokes": strokes,
+	});
+
+	let path = location.path("resampled.json")?;
+	std::fs::write(&path, doc.to_string())?;
+	Ok(path)
+}
+
+/// Every saved image in a class directory, in any of the output formats, numbered samples first in numeric order, then anything else by name.
+fn class_image_files(class_dir: &Path) -> Vec<PathBuf> {
+	let mut files: Vec<PathBuf> = class_files(class_dir)
+		.into_iter()
+		.filter(|path| path.extension().is_some_and(|ext| {
+			OutputFormat::ALL.iter().any(|format| ext.eq_ignore_ascii_case(format.extension()))
+		}))
+		.collect();
+	files.sort_by_key(|path| {
+		let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+		(leading_number(&stem).unwrap_or(u32::MAX), stem)
+	});
+	files
+}
+
+/// Load every saved image in the dataset as grayscale pixels, resized to `raster_size` if needed, one after another.
+/// Also returns each image's label, the index of its class in `gestures`.
+fn load_grayscale_samples(root_dir: &Path, gestures: &[String], raster_size: (u32, u32)) -> Result<(Vec<u8>, Vec<usize>), SaveError> {
+	let mut pixels: Vec<u8> = vec![];
+	let mut labels: Vec<usize> = vec![];
+	for (class_idx, class_name) in gestures.iter().enumerate() {
+		for file in class_image_files(&root_dir.join(class_name)) {
+			let mut img = stroke_intensity(&image::open(&file)?);
+			if img.dimensions() != raster_size {
+				img = image::imageops::resize(&img, raster_size.0, raster_size.1, image::imageops::FilterType::Triangle);
+			}
+			pixels.extend_from_slice(img.as_raw());
+			labels.push(class_idx);
+		}
+	}
+	Ok((pixels, labels))
+}
+
+/// Write every sample in the dataset as a pair of MNIST-style IDX files in the root directory.
+/// Images are converted to grayscale and resized to `raster_size` if needed.  Labels are each class's index in `gestures`.
+/// Returns the number of samples written.
+fn export_idx(root_dir: &Path, gestures: &[String], raster_size: (u32, u32)) -> Result<usize, SaveError> {
+	if gestures.len() > 256 {
+		return Err(SaveError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, "IDX labels are single bytes, so at most 256 classes can be exported")));
+	}
+
+	let (pixels, labels) = load_grayscale_samples(root_dir, gestures, raster_size)?;
+	let labels: Vec<u8> = labels.iter().map(|&l| l as u8).collect();
+
+	// IDX headers are a big-endian magic number (type 0x08 = unsigned byte, then the dimension count) and one u32 per dimension.
+	let count = labels.len() as u32;
+	let mut images_file: Vec<u8> = vec![];
+	images_file.extend_from_slice(&0x0000_0803u32.to_be_bytes());
+	images_file.extend_from_slice(&count.to_be_bytes());
+	images_file.extend_from_slice(&raster_size.1.to_be_bytes());
+	images_file.extend_from_slice(&raster_size.0.to_be_bytes());
+	images_file.extend_from_slice(&pixels);
+	std::fs::write(root_dir.join("images-idx3-ubyte"), images_file)?;
+
+	let mut labels_file: Vec<u8> = vec![];
+	labels_file.extend_from_slice(&0x0000_0801u32.to_be_bytes());
+	labels_file.extend_from_slice(&count.to_be_bytes());
+	labels_file.extend_from_slice(&labels);
+	std::fs::write(root_dir.join("labels-idx1-ubyte"), labels_file)?;
+
+	Ok(labels.len())
+}
+
+/// Write a NumPy `.npy` file (format version 1.0) holding `data` with the given dtype and shape.
+fn write_npy(path: &Path, descr: &str, shape: &[usize], data: &[u8]) -> Result<(), std::io::Error> {
+	// One element tuples need a trailing comma in Python.
+	let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+	let shape_str = if dims.len() == 1 { format!("({},)", dims[0]) } else { format!("({})", dims.join(", ")) };
+	let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape_str);
+	// The magic, version and length take 10 bytes.  The header is space padded so the data starts on a 64 byte boundary.
+	let unpadded = 10 + header.len() + 1;
+	header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+	header.push('\n');
+
+	let mut file: Vec<u8> = b"\x93NUMPY\x01\x00".to_vec();
+	file.extend_from_slice(&(header.len() as u16).to_le_bytes());
+	file.extend_from_slice(header.as_bytes());
+	file.extend_from_slice(data);
+	std::fs::write(path, file)
+}
+
+/// Write the dataset to the root directory as `gestures.npy`, a (N, height, width) uint8 array,
+/// and `gestures_labels.npy`, a (N,) int64 array of class indices into `gestures`.  Returns the number of samples written.
+fn export_npy(root_dir: &Path, gestures: &[String], raster_size: (u32, u32)) -> Result<usize, SaveError> {
+	let (pixels, labels) = load_grayscale_samples(root_dir, gestures, raster_size)?;
+	write_npy(&root_dir.join("gestures.npy"), "|u1", &[labels.len(), raster_size.1 as usize, raster_size.0 as usize], &pixels)?;
+	let label_bytes: Vec<u8> = labels.iter().flat_map(|&l| (l as i64).to_le_bytes()).collect();
+	write_npy(&root_dir.join("gestures_labels.npy"), "<i8", &[labels.len()], &label_bytes)?;
+	Ok(labels.len())
+}
+
+/// Write `dataset.json` to the root directory: the classes in order with their label indices, the raster size and the app version.
+/// The creation time of an existing file is kept, so it records when collection started.
+fn write_dataset_metadata(root_dir: &Path, gestures: &[String], raster_size: (u32, u32)) -> Result<PathBuf, SaveError> {
+	let path = root_dir.join("dataset.json");
+	let now = unix_timestamp();
+	let created = std::fs::read_to_string(&path).ok()
+		.and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+		.and_then(|doc| doc["created"].as_u64())
+		.unwrap_or(now);
+	let class_to_index: serde_json::Map<String, serde_json::Value> = gestures.iter().enumerate()
+		.map(|(idx, name)| (name.clone(), serde_json::json!(idx)))
+		.collect();
+	let doc = serde_json::json!({
+		"classes": gestures,
+		"class_to_index": class_to_index,
+		"width": raster_size.0,
+		"height": raster_size.1,
+		"version": env!("CARGO_PKG_VERSION"),
+		"created": created,
+		"updated": now,
+	});
+	std::fs::write(&path, serde_json::to_string_pretty(&doc).unwrap_or_else(|_| doc.to_string()))?;
+	Ok(path)
+}
+
+/// The positions and pressures of a drawing's strokes, laid out alike.
+type SampleStrokes = (Vec<Vec<egui::Pos2>>, Vec<Vec<f32>>);
+
+/// The strokes and pressures saved in a sample's stroke JSON, with positions as normalized when saved.
+/// None for anything else: other files, resampled strokes, tags, bounds, and shuffled-order copies, which repeat their original's drawing.
+fn read_sample_strokes(file: &Path) -> Result<Option<SampleStrokes>, SaveError> {
+	let name = file.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+	if !name.ends_with(".json") || name.ends_with(".resampled.json") || name.ends_with(".tags.json") || name.ends_with(".bounds.json") {
+		return Ok(None);
+	}
+	let doc: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(file)?)
+		.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+	let strokes = match doc["strokes"].as_array() {
+		Some(strokes) => strokes,
+		None => return Ok(None),
+	};
+	let in_order = doc["stroke_order"].as_array().is_none_or(|order| order.iter().enumerate().all(|(i, idx)| idx.as_u64() == Some(i as u64)));
+	if !in_order {
+		return Ok(None);
+	}
+	let point = |p: &serde_json::Value, key: &str| p[key].as_f64().unwrap_or(0.0) as f32;
+	let lines = strokes.iter()
+		.map(|line| line.as_array().map_or(vec![], |pts| pts.iter().map(|p| egui::pos2(point(p, "x"), point(p, "y"))).collect()))
+		.collect();
+	let pressures = strokes.iter()
+		.map(|line| line.as_array().map_or(vec![], |pts| pts.iter().map(|p| p["pressure"].as_f64().unwrap_or(1.0) as f32).collect()))
+		.collect();
+	Ok(Some((lines, pressures)))
+}
+
+/// A recognizer template for every sample with stroke JSON, in every class.
+fn load_templates(root_dir: &Path, gestures: &[String]) -> Result<Vec<Template>, SaveError> {
+	let mut templates = vec![];
+	for class_name in gestures {
+		for file in class_files(&root_dir.join(class_name)) {
+			if let Some((lines, _)) = read_sample_strokes(&file)? {
+				templates.extend(Template::new(class_name, &lines));
+			}
+		}
+	}
+	Ok(templates)
+}
+
+/// Rasterize every sample again from its stroke JSON, replacing its image, so the output size or options can change after collection.
+/// The JSON holds the points as already normalized, and they're normalized again here with the current options.  Where the drawing
+/// sat on the canvas is gone by then, so a changed aspect ratio, padding or normalization mode reframes it around its saved points.
+/// Flipped copies are redrawn from the same points.  Rotated copies can't be, since their angles weren't saved, so a dataset with
+/// any is refused before anything changes.  The sample's image in any other output format is removed, so each sample still has one
+/// image, and the stroke JSON is updated to the new size.  Returns the number of samples redrawn.
+fn rerender_dataset(root_dir: &Path, gestures: &[String], raster_size: (u32, u32), options: &RasterOptions, background: Option<&image::RgbImage>) -> Result<usize, SaveError> {
+	for class_name in gestures {
+		if let Some(rotated) = class_image_files(&root_dir.join(class_name)).iter().find(|file| is_rotated_copy(file)) {
+			return Err(SaveError::Io(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!(
+				"{} is a rotated copy, and its angle wasn't saved, so it can't be redrawn.  Remove the rotated copies first", rotated.display()))));
+		}
+	}
+
+	let mut count = 0;
+	let mut rng = rand::thread_rng();
+	let mut redraw = |lines: &[Vec<egui::Pos2>], pressures: &[Vec<f32>], base: &Path| -> Result<(), SaveError> {
+		let mut img = rasterize(lines, pressures, raster_size, options);
+		if let Some(background) = background {
+			img = composite_background(&img, background, options);
+		}
+		add_noise(&mut img, options, &mut rng);
+		for format in OutputFormat::ALL.iter().filter(|format| **format != options.output_format) {
+			let stale = base.with_extension(format.extension());
+			if stale.exists() {
+				std::fs::remove_file(stale)?;
+			}
+		}
+		img.save_with_format(base.with_extension(options.output_format.extension()), options.output_format.image_format())?;
+		Ok(())
+	};
+	for class_name in gestures {
+		for file in class_files(&root_dir.join(class_name)) {
+			set_stroke_json_size(&file, raster_size)?;
+			let (lines, pressures) = match read_sample_strokes(&file)? {
+				Some(strokes) => strokes,
+				None => continue,
+			};
+			redraw(&lines, &pressures, &file)?;
+			let stem = file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+			for (flip_h, flip_v, suffix) in FLIP_AUGMENTS {
+				let copy = file.with_file_name(format!("{}{}.json", stem, suffix));
+				if OutputFormat::ALL.iter().any(|format| copy.with_extension(format.extension()).exists()) {
+					redraw(&flip_strokes(&lines, flip_h, flip_v), &pressures, &copy)?;
+				}
+			}
+			count += 1;
+		}
+	}
+	Ok(count)
+}
+
+/// Whether an image is one of the randomly rotated copies saved with a sample, e.g. "12_rot0.png".
+fn is_rotated_copy(file: &Path) -> bool {
+	let stem = file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+	stem.rsplit_once("_rot").is_some_and(|(_, i)| !i.is_empty() && i.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Record a new raster size in a stroke JSON, original or shuffled-order copy.  Any other file is left alone.
+fn set_stroke_json_size(file: &Path, raster_size: (u32, u32)) -> Result<(), SaveError> {
+	if file.extension().is_none_or(|ext| ext != "json") {
+		return Ok(());
+	}
+	let mut doc: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(file)?)
+		.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+	if !doc["strokes"].is_array() {
+		return Ok(());
+	}
+	doc["width"] = serde_json::json!(raster_size.0);
+	doc["height"] = serde_json::json!(raster_size.1);
+	std::fs::write(file, doc.to_string())?;
+	Ok(())
+}
+
+/// Folders "Split dataset" copies samples into, inside the dataset folder.
+const SPLIT_NAMES: [&str; 3] = ["train", "val", "test"];
+
+/// Copy every sample into `train/<class>`, `val/<class>` and `test/<class>` under the root, in the proportions given by `fractions`
+/// (train and validation, with the rest going to test).  Each class is split on its own, so every split keeps the class balance.
+/// All files of a sample, augmented copies and sidecars included, go to the same split, or augments would leak between them.
+/// The same seed always gives the same split.  Existing split folders are refused rather than mixed with a new split.
+/// Returns how many samples went to each split.
+fn split_dataset(root_dir: &Path, gestures: &[String], pattern: &str, fractions: (f32, f32), seed: u64) -> Result<[usize; 3], SaveError> {
+	let in_use = |message: String| SaveError::Io(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message));
+	for name in SPLIT_NAMES {
+		if gestures.iter().any(|g| g == name) {
+			return Err(in_use(format!("'{}' is a class, so it can't also be a split folder", name)));
+		}
+		if root_dir.join(name).exists() {
+			return Err(in_use(format!("{} already exists.  Remove it first", root_dir.join(name).display())));
+		}
+	}
+
+	let mut counts = [0; 3];
+	for class_name in gestures {
+		// ChaCha8 output is fixed for a given seed, unlike StdRng's.  Each class gets its own stream, so adding or
+		// reordering classes doesn't reshuffle the others.
+		let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(seed ^ stable_hash(class_name));
+		let mut samples: std::collections::BTreeMap<u32, Vec<PathBuf>> = std::collections::BTreeMap::new();
+		for file in class_files(&root_dir.join(class_name)) {
+			let name = file.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+			if let Some(number) = sample_number_from_name(pattern, class_name, &name) {
+				samples.entry(number).or_default().push(file);
+			}
+		}
+		// The map is sorted by sample number, so the shuffle only depends on the seed and the files present.
+		let mut samples: Vec<Vec<PathBuf>> = samples.into_values().collect();
+		samples.shuffle(&mut rng);
+		let train_end = (samples.len() as f32 * fractions.0).round() as usize;
+		let val_end = (train_end + (samples.len() as f32 * fractions.1).round() as usize).min(samples.len());
+		for (idx, files) in samples.iter().enumerate() {
+			let split = if idx < train_end { 0 } else if idx < val_end { 1 } else { 2 };
+			let dir = root_dir.join(SPLIT_NAMES[split]).join(class_name);
+			std::fs::create_dir_all(&dir)?;
+			for file in files {
+				if let Some(name) = file.file_name() {
+					std::fs::copy(file, dir.join(name))?;
+				}
+			}
+			counts[split] += 1;
+		}
+	}
+	Ok(counts)
+}
+
+/// FNV-1a hash of a string.  Unlike std's hasher, it's guaranteed to be the same in every build.
+fn stable_hash(text: &str) -> u64 {
+	text.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// Folder "Export ImageFolder" writes its class folders into, inside the dataset folder.
+const IMAGE_FOLDER_NAME: &str = "dataset";
+
+/// Copy every image, augmented copies included, into a fresh `dataset/<class>/<n>.png` tree that `torchvision.datasets.ImageFolder`
+/// reads as is.  Each class is numbered from 0 in the same order as the other exports, and everything is converted to PNG.
+/// Images are resized to `resize` if given.  An existing export is refused rather than mixed with this one.  Returns the number of images.
+fn export_image_folder(root_dir: &Path, gestures: &[String], resize: Option<(u32, u32)>) -> Result<usize, SaveError> {
+	let in_use = |message: String| SaveError::Io(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message));
+	let out_dir = root_dir.join(IMAGE_FOLDER_NAME);
+	if gestures.iter().any(|g| g == IMAGE_FOLDER_NAME) {
+		return Err(in_use(format!("'{}' is a class, so it can't also be the export folder", IMAGE_FOLDER_NAME)));
+	}
+	if out_dir.exists() {
+		return Err(in_use(format!("{} already exists.  Remove it first", out_dir.display())));
+	}
+
+	let mut count = 0;
+	for class_name in gestures {
+		let class_dir = out_dir.join(class_name);
+		std::fs::create_dir_all(&class_dir)?;
+		for (idx, file) in class_image_files(&root_dir.join(class_name)).iter().enumerate() {
+			let mut img = image::open(file)?;
+			if let Some((w, h)) = resize.filter(|size| *size != img.dimensions()) {
+				img = img.resize_exact(w, h, image::imageops::FilterType::Triangle);
+			}
+			img.save_with_format(class_dir.join(format!("{}.png", idx)), image::ImageFormat::Png)?;
+			count += 1;
+		}
+	}
+	Ok(count)
+}
+
+/// Tile every image in a class into one grayscale picture, `columns` to a row, and save it as `{class}_contact.png` in the root.
+/// It goes in the root rather than the class folder so it's never mistaken for a sample.
+/// Images are shrunk or stretched to the size of the first, with a dark gutter between them.
+fn make_contact_sheet(root_dir: &Path, class_name: &str, columns: u32) -> Result<PathBuf, SaveError> {
+	let files = class_image_files(&class_dir(root_dir, class_name)?);
+	if files.is_empty() {
+		return Err(SaveError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, format!("'{}' has no images", class_name))));
+	}
+	let gutter = 1;
+	let mut sheet: Option<image::GrayImage> = None;
+	let mut tile_size = (0, 0);
+	for (idx, file) in files.iter().enumerate() {
+		let mut tile = stroke_intensity(&image::open(file)?);
+		let sheet = sheet.get_or_insert_with(|| {
+			tile_size = tile.dimensions();
+			let rows = (files.len() as u32).div_ceil(columns);
+			let cols = columns.min(files.len() as u32);
+			image::GrayImage::from_pixel(cols * (tile_size.0 + gutter) + gutter, rows * (tile_size.1 + gutter) + gutter, image::Luma([64]))
+		});
+		if tile.dimensions() != tile_size {
+			tile = image::imageops::resize(&tile, tile_size.0, tile_size.1, image::imageops::FilterType::Triangle);
+		}
+		let (col, row) = (idx as u32 % columns, idx as u32 / columns);
+		image::imageops::replace(sheet, &tile, gutter + col * (tile_size.0 + gutter), gutter + row * (tile_size.1 + gutter));
+	}
+
+	let path = root_dir.join(format!("{}_contact.png", class_name));
+	sheet.unwrap().save_with_format(&path, image::ImageFormat::Png)?;
+	Ok(path)
+}
+
+/// Write `manifest.csv` to the root directory, listing every saved image with its class and dimensions.
+/// Paths are relative to the root so the dataset can be moved.  Classes without samples simply have no rows.
+/// The drawing's canvas bounds come from the image's bounds sidecar, or its stroke JSON for samples saved before there were
+/// sidecars, and are left empty for augmented copies.
+fn export_manifest(root_dir: &Path, gestures: &[String]) -> Result<PathBuf, SaveError> {
+	let mut csv = String::from("filepath,class,class_index,width,height,min_x,min_y,max_x,max_y,aspect,tags\n");
+	for (class_idx, class_name) in gestures.iter().enumerate() {
+		for file in class_image_files(&root_dir.join(class_name)) {
+			let (w, h) = image::image_dimensions(&file)?;
+			let relative = file.strip_prefix(root_dir).unwrap_or(&file);
+			let doc: serde_json::Value = [file.with_extension("bounds.json"), file.with_extension("json")].iter()
+				.find_map(|sidecar| std::fs::read_to_string(sidecar).ok())
+				.and_then(|text| serde_json::from_str(&text).ok())
+				.unwrap_or_default();
+			let bounds: Vec<String> = ["min_x", "min_y", "max_x", "max_y", "aspect"].iter()
+				.map(|key| doc["canvas_bounds"][key].as_f64().map(|v| v.to_string()).unwrap_or_default())
+				.collect();
+			// Tags can't hold commas, so semicolons keep them in one column.
+			let tags: serde_json::Value = std::fs::read_to_string(file.with_extension("tags.json")).ok()
+				.and_then(|text| serde_json::from_str(&text).ok())
+				.unwrap_or_default();
+			let tags: Vec<&str> = tags["tags"].as_array().map_or(vec![], |tags| tags.iter().filter_map(|tag| tag.as_str()).collect());
+			csv.push_str(&format!("{},{},{},{},{},{},{}\n", relative.display(), class_name, class_idx, w, h, bounds.join(","), tags.join(";")));
+		}
+	}
+
+	let path = root_dir.join("manifest.csv");
+	std::fs::write(&path, csv)?;
+	Ok(path)
 }
 
 fn main() {
-	let app = GestureDatasetApp::default();
+	let launch_args = match LaunchArgs::parse(std::env::args().skip(1)) {
+		Ok(launch_args) => launch_args,
+		Err(message) => {
+			eprintln!("{}", message);
+			std::process::exit(2);
+		}
+	};
+	// Make the class folders now, so they're found like any other class when the app scans the root.
+	for dir in launch_args.class_dirs() {
+		if let Err(e) = ensure_writable_dir(&dir) {
+			eprintln!("Failed to create {}: {}", dir.display(), e);
+			std::process::exit(1);
+		}
+	}
+
+	let app = GestureDatasetApp { launch_args, ..Default::default() };
 	let native_options = eframe::NativeOptions::default();
 	eframe::run_native(Box::new(app), native_options);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn npy_header_is_aligned() {
+		let path = std::env::temp_dir().join(format!("npy_header_test_{}.npy", std::process::id()));
+		write_npy(&path, "|u1", &[3], &[7, 8, 9]).unwrap();
+		let bytes = std::fs::read(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+		let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+		assert_eq!((10 + header_len) % 64, 0);
+		let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+		assert!(header.starts_with("{'descr': '|u1', 'fortran_order': False, 'shape': (3,), }"));
+		assert!(header.ends_with('\n'));
+		assert_eq!(&bytes[10 + header_len..], &[7, 8, 9]);
+	}
+
+	#[test]
+	fn npy_shape_lists_every_dimension() {
+		let path = std::env::temp_dir().join(format!("npy_shape_test_{}.npy", std::process::id()));
+		write_npy(&path, "|u1", &[2, 1, 3], &[0; 6]).unwrap();
+		let bytes = std::fs::read(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+		assert!(String::from_utf8_lossy(&bytes).contains("'shape': (2, 1, 3)"));
+	}
+
+	#[test]
+	fn sample_names_round_trip() {
+		for pattern in ["{n}", "{class}_{n:05}", "{timestamp}-{class}-{n}", "sample"] {
+			let name = format_sample_name(pattern, "circle", 42, 1_700_000_000);
+			assert_eq!(sample_number_from_name(pattern, "circle", &format!("{}.png", name)), Some(42), "{}", pattern);
+			assert_eq!(sample_number_from_name(pattern, "circle", &format!("{}_fliph.png", name)), Some(42), "{}", pattern);
+		}
+		assert_eq!(format_sample_name("{class}_{n:05}", "circle", 42, 0), "circle_00042");
+		assert_eq!(sample_number_from_name("{class}_{n}", "circle", "square_3.png"), None);
+	}
+
+	#[test]
+	fn bad_patterns_fall_back_to_the_number() {
+		assert!(filename_pattern_error("../{n}").is_some());
+		assert_eq!(format_sample_name("../{n}", "circle", 7, 0), "7");
+		assert_eq!(format_sample_name("{unknown}{n}", "circle", 7, 0), "{unknown}7");
+	}
+}
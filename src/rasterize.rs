@@ -0,0 +1,556 @@
+//! Turning strokes into images.  This is everything the app does to a drawing between the canvas and the saved file,
+//! without any of the GUI, so strokes can also be re-rendered from code.
+
+use eframe::egui;
+use image::{GenericImageView, ImageFormat, Luma, Rgb, Rgba};
+use rand::Rng;
+
+/// File format of the saved sample images.
+/// WEBP isn't offered because the image crate can only decode it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
+pub enum OutputFormat {
+	Png,
+	Bmp,
+	Jpeg,
+}
+
+impl OutputFormat {
+	pub const ALL: [OutputFormat; 3] = [OutputFormat::Png, OutputFormat::Bmp, OutputFormat::Jpeg];
+
+	pub fn image_format(self) -> ImageFormat {
+		match self {
+			OutputFormat::Png => ImageFormat::Png,
+			OutputFormat::Bmp => ImageFormat::Bmp,
+			OutputFormat::Jpeg => ImageFormat::Jpeg,
+		}
+	}
+
+	pub fn extension(self) -> &'static str {
+		match self {
+			OutputFormat::Png => "png",
+			OutputFormat::Bmp => "bmp",
+			OutputFormat::Jpeg => "jpg",
+		}
+	}
+
+	pub fn name(self) -> &'static str {
+		match self {
+			OutputFormat::Png => "PNG",
+			OutputFormat::Bmp => "BMP",
+			OutputFormat::Jpeg => "JPEG",
+		}
+	}
+}
+
+/// How the drawing is positioned and scaled to fill the output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
+pub enum Normalization {
+	// Stretch the drawing's bounding box to the edges.
+	BoundingBox,
+	// Center on the mean point and scale by the spread of the points, so a single stray point can't shrink the rest.
+	Centroid,
+	// Center the drawing but keep its size on the canvas, so big and small drawings stay big and small.
+	FixedScale,
+}
+
+/// Controls how the strokes are mapped into and drawn onto the output raster.
+#[derive(Clone)]
+#[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "persistence", serde(default))]
+pub struct RasterOptions {
+	pub line_thickness: u32,
+	pub antialias: bool,
+	// Also stamp each stroke's vertices so sharp corners and stroke ends have no gaps.
+	pub rounded_joins: bool,
+	pub preserve_aspect: bool,
+	pub normalization: Normalization,
+	// Blank border, in output pixels, kept between the drawing and each edge of the raster.
+	pub padding: u32,
+	pub output_grayscale: bool,
+	// Black strokes on a white background instead of white on black.
+	pub invert_colors: bool,
+	// Opaque strokes on a transparent background, with coverage as alpha.  Takes precedence over grayscale output.
+	pub output_rgba: bool,
+	// Draw at this multiple of the output size and average down.  One turns supersampling off.
+	pub supersample_factor: u32,
+	// Stamps placed along each segment per output pixel it covers.  One is enough to leave no gaps.
+	pub stamps_per_pixel: u32,
+	// Rounds of Chaikin corner cutting applied to each stroke before drawing.  Zero leaves them as captured.
+	pub smoothing_iterations: u32,
+	// Scale the line thickness by pen pressure, so harder presses draw thicker lines.
+	pub pressure_thickness: bool,
+	// Fill strokes that end within `close_distance` output pixels of where they started, so closed outlines become silhouettes.
+	pub fill_closed: bool,
+	pub close_distance: f32,
+	// Coverage is raised to this power before becoming pixel values.  Above one thins and fades soft edges, below one darkens them.
+	pub gamma: f32,
+	// Strength of the noise `add_noise` mixes into saved images, from 0 (none) to 1.  `rasterize` itself never adds any.
+	pub noise_level: f32,
+	// Salt-and-pepper noise, flipping random pixels fully on or off, instead of Gaussian noise on every pixel.
+	pub salt_and_pepper: bool,
+	// Repeat a background picture at its own size instead of stretching it over the raster.
+	pub tile_background: bool,
+	pub output_format: OutputFormat,
+}
+
+impl Default for RasterOptions {
+	fn default() -> Self {
+		Self {
+			line_thickness: 1,
+			antialias: false,
+			rounded_joins: false,
+			preserve_aspect: false,
+			normalization: Normalization::BoundingBox,
+			padding: 0,
+			output_grayscale: false,
+			invert_colors: false,
+			output_rgba: false,
+			supersample_factor: 1,
+			stamps_per_pixel: 1,
+			smoothing_iterations: 0,
+			pressure_thickness: false,
+			fill_closed: false,
+			close_distance: 2.0,
+			gamma: 1.0,
+			noise_level: 0.0,
+			salt_and_pepper: false,
+			tile_background: false,
+			output_format: OutputFormat::Png,
+		}
+	}
+}
+
+/// Per-pixel stroke coverage in the 0..1 range.  Converted to the output image once all strokes are drawn.
+type CoverageMap = image::ImageBuffer<image::Luma<f32>, Vec<f32>>;
+
+/// Add `amount` of coverage to a pixel, skipping anything that falls off the image.
+fn add_coverage(coverage: &mut CoverageMap, x: i64, y: i64, amount: f32) {
+	if x < 0 || y < 0 || x >= coverage.width() as i64 || y >= coverage.height() as i64 { return; }
+	let pxl = coverage.get_pixel_mut(x as u32, y as u32);
+	pxl.0[0] = (pxl.0[0] + amount).min(1.0);
+}
+
+/// Shrink a coverage map by `factor` on each axis, averaging each factor by factor block into one pixel.
+fn box_downsample(coverage: &CoverageMap, factor: u32) -> CoverageMap {
+	let area = (factor * factor) as f32;
+	CoverageMap::from_fn(coverage.width() / factor, coverage.height() / factor, |x, y| {
+		let mut sum = 0.0;
+		for oy in 0..factor {
+			for ox in 0..factor {
+				sum += coverage.get_pixel(x*factor + ox, y*factor + oy).0[0];
+			}
+		}
+		Luma([sum / area])
+	})
+}
+
+/// Fill the inside of a closed polygon, given in raster pixel units, by scanning each row of pixel centers.
+/// Self-intersecting outlines use the even-odd rule, so the overlap of a figure eight stays empty.
+fn fill_polygon(coverage: &mut CoverageMap, points: &[(f32, f32)]) {
+	for y in 0..coverage.height() {
+		let cy = y as f32 + 0.5;
+		// Where this row crosses each edge.  The closing edge from the last point back to the first is included.
+		let mut crossings: Vec<f32> = points.iter().zip(points.iter().cycle().skip(1))
+			.filter(|((_, ay), (_, by))| (*ay <= cy) != (*by <= cy))
+			.map(|((ax, ay), (bx, by))| ax + (cy - ay) / (by - ay) * (bx - ax))
+			.collect();
+		crossings.sort_by(|a, b| a.total_cmp(b));
+		for span in crossings.chunks_exact(2) {
+			let first = (span[0] - 0.5).ceil().max(0.0) as i64;
+			let last = (span[1] - 0.5).floor() as i64;
+			for x in first..=last {
+				add_coverage(coverage, x, y as i64, 1.0);
+			}
+		}
+	}
+}
+
+/// Fill a disk with a diameter of `thickness` pixels around the point (fx, fy), given in raster pixel units.
+/// A thickness of one covers only the center pixel.
+/// When anti-aliasing, each covered pixel is instead splatted bilinearly onto its four neighbors.
+fn stamp_disk(coverage: &mut CoverageMap, fx: f32, fy: f32, thickness: f32, antialias: bool) {
+	if coverage.width() == 0 || coverage.height() == 0 { return; }
+	// Points on the max bound normalize to exactly the image size, one past the last pixel.  Pull them back inside.
+	// Points further out are left alone, so strokes running off the image get clipped instead of piling up on the edge.
+	let pull_inside = |f: f32, size: f32| if f >= size && f < size + 1e-3 { size - 1e-3 } else { f };
+	let fx = pull_inside(fx, coverage.width() as f32);
+	let fy = pull_inside(fy, coverage.height() as f32);
+	let radius = thickness / 2.0;
+	let reach = radius.floor() as i64;
+	for oy in -reach..=reach {
+		for ox in -reach..=reach {
+			if (ox*ox + oy*oy) as f32 > radius*radius { continue; }
+			if antialias {
+				// Pixel centers sit at +0.5, so shift back before splitting the point between its neighbors.
+				let gx = fx - 0.5 + ox as f32;
+				let gy = fy - 0.5 + oy as f32;
+				let (x0, y0) = (gx.floor(), gy.floor());
+				let (tx, ty) = (gx - x0, gy - y0);
+				let (x0, y0) = (x0 as i64, y0 as i64);
+				add_coverage(coverage, x0, y0, (1.0 - tx) * (1.0 - ty));
+				add_coverage(coverage, x0 + 1, y0, tx * (1.0 - ty));
+				add_coverage(coverage, x0, y0 + 1, (1.0 - tx) * ty);
+				add_coverage(coverage, x0 + 1, y0 + 1, tx * ty);
+			} else {
+				add_coverage(coverage, fx.floor() as i64 + ox, fy.floor() as i64 + oy, 1.0);
+			}
+		}
+	}
+}
+
+/// Smooth a polyline with Chaikin's corner cutting: every segment is replaced by points at 1/4 and 3/4 along it.
+/// The endpoints are kept so the stroke doesn't shrink.
+pub fn chaikin_smooth(line: &[egui::Pos2], iterations: u32) -> Vec<egui::Pos2> {
+	let mut current = line.to_vec();
+	for _ in 0..iterations {
+		if current.len() < 3 {
+			break;
+		}
+		let mut next = Vec::with_capacity(current.len() * 2);
+		next.push(current[0]);
+		for w in current.windows(2) {
+			next.push(w[0] + (w[1] - w[0]) * 0.25);
+			next.push(w[0] + (w[1] - w[0]) * 0.75);
+		}
+		next.push(*current.last().unwrap());
+		current = next;
+	}
+	current
+}
+
+/// Shortest distance from `p` to the segment between `a` and `b`.
+pub fn point_segment_distance(p: egui::Pos2, a: egui::Pos2, b: egui::Pos2) -> f32 {
+	let ab = b - a;
+	let len_sq = ab.length_sq();
+	let ap = p - a;
+	let t = if len_sq > 0.0 { ((ap.x*ab.x + ap.y*ab.y) / len_sq).clamp(0.0, 1.0) } else { 0.0 };
+	(a + ab * t).distance(p)
+}
+
+/// Which points of a polyline Ramer-Douglas-Peucker simplification keeps, in order.
+/// A point survives if dropping it would move the line by more than `epsilon`.  The endpoints always survive.
+pub fn simplify_indices(line: &[egui::Pos2], epsilon: f32) -> Vec<usize> {
+	if line.len() < 3 || epsilon <= 0.0 {
+		return (0..line.len()).collect();
+	}
+	let mut keep = vec![false; line.len()];
+	keep[0] = true;
+	keep[line.len() - 1] = true;
+	// Spans still to check, as (first, last) points.  A stack instead of recursion, since strokes can be long.
+	let mut spans = vec![(0, line.len() - 1)];
+	while let Some((first, last)) = spans.pop() {
+		let farthest = (first + 1..last)
+			.map(|i| (i, point_segment_distance(line[i], line[first], line[last])))
+			.max_by(|a, b| a.1.total_cmp(&b.1));
+		if let Some((i, distance)) = farthest {
+			if distance > epsilon {
+				keep[i] = true;
+				spans.push((first, i));
+				spans.push((i, last));
+			}
+		}
+	}
+	(0..line.len()).filter(|&i| keep[i]).collect()
+}
+
+/// Simplify a polyline with Ramer-Douglas-Peucker.  See `simplify_indices`.
+pub fn simplify(line: &[egui::Pos2], epsilon: f32) -> Vec<egui::Pos2> {
+	simplify_indices(line, epsilon).into_iter().map(|i| line[i]).collect()
+}
+
+/// Mirror the strokes horizontally and/or vertically.  Normalization moves them back into place afterwards.
+pub fn flip_strokes(lines: &[Vec<egui::Pos2>], flip_h: bool, flip_v: bool) -> Vec<Vec<egui::Pos2>> {
+	let sx = if flip_h { -1.0 } else { 1.0 };
+	let sy = if flip_v { -1.0 } else { 1.0 };
+	lines.iter().map(|line| line.iter().map(|p| egui::pos2(p.x * sx, p.y * sy)).collect()).collect()
+}
+
+/// Rotate the strokes by `angle` radians around the center of their bounding box.
+pub fn rotate_strokes(lines: &[Vec<egui::Pos2>], angle: f32) -> Vec<Vec<egui::Pos2>> {
+	let points = lines.iter().flatten();
+	let (min, max) = points.fold((egui::pos2(f32::MAX, f32::MAX), egui::pos2(f32::MIN, f32::MIN)), |(min, max), p| (min.min(*p), max.max(*p)));
+	let center = min + (max - min) / 2.0;
+	let rot = egui::emath::Rot2::from_angle(angle);
+	lines.iter().map(|line| line.iter().map(|p| center + rot * (*p - center)).collect()).collect()
+}
+
+/// Offset and size, in output pixels, of the area inside the padding that strokes get mapped into.
+/// Padding is never allowed to eat the whole image.
+pub fn padded_area(raster_size: (u32, u32), padding: u32) -> (f32, f32, f32, f32) {
+	let pad_x = padding.min(raster_size.0.saturating_sub(1) / 2);
+	let pad_y = padding.min(raster_size.1.saturating_sub(1) / 2);
+	(pad_x as f32, pad_y as f32, (raster_size.0 - 2*pad_x) as f32, (raster_size.1 - 2*pad_y) as f32)
+}
+
+/// Remap the strokes from canvas space into the 0/1 range using the bounds of the drawing.
+/// `aspect` is the width/height of the area the result will be drawn into, used when preserving aspect ratio.
+pub fn normalize_strokes(lines: &[Vec<egui::Pos2>], aspect: f32, options: &RasterOptions) -> Vec<Vec<egui::Pos2>> {
+	// Lines will be all over the place, so we want to remap them to the appropriate size.
+	// Find the bounds of the drawing and remap them to the edges of the image.
+	let bounding_box = || {
+		let mut bounds = (1e32, -1e32, 1e32, -1e32);
+		for pt in lines.iter().flatten() {
+			bounds.0 = pt.x.min(bounds.0);
+			bounds.1 = pt.x.max(bounds.1);
+			bounds.2 = pt.y.min(bounds.2);
+			bounds.3 = pt.y.max(bounds.3);
+		}
+		bounds
+	};
+	let (mut min_x, max_x, mut min_y, max_y) = match options.normalization {
+		Normalization::BoundingBox => bounding_box(),
+		Normalization::FixedScale => {
+			// A window one canvas unit across the output's shorter side, centered on the drawing.  Whatever falls outside is clipped.
+			let (x0, x1, y0, y1) = bounding_box();
+			let (half_x, half_y) = (aspect.max(1.0) / 2.0, (1.0 / aspect).max(1.0) / 2.0);
+			let (cx, cy) = ((x0 + x1) / 2.0, (y0 + y1) / 2.0);
+			(cx - half_x, cx + half_x, cy - half_y, cy + half_y)
+		}
+		Normalization::Centroid => {
+			// Two standard deviations either side of the mean covers most of a drawing without letting a stray point set the scale.
+			let count = lines.iter().map(|line| line.len()).sum::<usize>().max(1) as f32;
+			let mean = lines.iter().flatten().fold(egui::Vec2::ZERO, |sum, pt| sum + pt.to_vec2()) / count;
+			let variance = lines.iter().flatten().fold(egui::Vec2::ZERO, |sum, pt| {
+				let d = pt.to_vec2() - mean;
+				sum + egui::vec2(d.x*d.x, d.y*d.y)
+			}) / count;
+			let (spread_x, spread_y) = (2.0 * variance.x.sqrt(), 2.0 * variance.y.sqrt());
+			(mean.x - spread_x, mean.x + spread_x, mean.y - spread_y, mean.y + spread_y)
+		}
+	};
+
+	// A dot or a perfectly horizontal/vertical line has no extent on one or both axes.
+	// Borrow the span of the other axis (or a unit span for a dot) and center the content on it.
+	let mut span_x = max_x - min_x;
+	let mut span_y = max_y - min_y;
+	let min_span = 1e-6;
+	if span_x < min_span && span_y < min_span {
+		span_x = 1.0;
+		span_y = 1.0;
+	} else if span_x < min_span {
+		span_x = span_y;
+	} else if span_y < min_span {
+		span_y = span_x;
+	}
+	min_x = (min_x + max_x - span_x) / 2.0;
+	min_y = (min_y + max_y - span_y) / 2.0;
+	span_x += 1e-6;
+	span_y += 1e-6;
+
+	// By default each axis is stretched to fill the target.
+	// When preserving aspect, both axes share the larger scale and the shorter one is centered with padding.
+	if options.preserve_aspect {
+		let scale = (span_x / aspect).max(span_y);
+		let (padded_x, padded_y) = (scale * aspect, scale);
+		min_x -= (padded_x - span_x) / 2.0;
+		min_y -= (padded_y - span_y) / 2.0;
+		span_x = padded_x;
+		span_y = padded_y;
+	}
+
+	// Outliers beyond the centroid's spread are pinned to the edge rather than drawn off the image.
+	let clamp = options.normalization == Normalization::Centroid;
+	lines.iter().map(|line| {
+		line.iter().map(|pt| {
+			let p = egui::pos2((pt.x - min_x) / span_x, (pt.y - min_y) / span_y);
+			if clamp { p.clamp(egui::Pos2::ZERO, egui::pos2(1.0, 1.0)) } else { p }
+		}).collect()
+	}).collect()
+}
+
+/// Normalize the strokes and draw them into an image of `raster_size`.  This is exactly what gets saved.
+/// `pressures` holds a value per point of `lines` and is only used with `pressure_thickness`.  Missing values count as full pressure.
+pub fn rasterize(lines: &[Vec<egui::Pos2>], pressures: &[Vec<f32>], raster_size: (u32, u32), options: &RasterOptions) -> image::DynamicImage {
+	let smoothed: Vec<Vec<egui::Pos2>> = lines.iter().map(|line| chaikin_smooth(line, options.smoothing_iterations)).collect();
+	let lines = &smoothed[..];
+	// Chaikin works on each coordinate separately, so smoothing pressure as the x of a point keeps it in step with the stroke.
+	let pressures: Vec<Vec<f32>> = pressures.iter().map(|line| {
+		let as_points: Vec<egui::Pos2> = line.iter().map(|p| egui::pos2(*p, 0.0)).collect();
+		chaikin_smooth(&as_points, options.smoothing_iterations).iter().map(|p| p.x).collect()
+	}).collect();
+	// Supersampling draws everything this many times larger, then averages it back down to the output size.
+	let factor = options.supersample_factor.max(1);
+	let output_size = raster_size;
+	let raster_size = (output_size.0 * factor, output_size.1 * factor);
+	let thickness_at = |line_idx: usize, point: f32| -> f32 {
+		let thickness = (options.line_thickness * factor) as f32;
+		if !options.pressure_thickness {
+			return thickness;
+		}
+		let line = match pressures.get(line_idx) {
+			Some(line) if !line.is_empty() => line,
+			_ => return thickness,
+		};
+		// Interpolate between neighboring points, since stamps are placed partway along segments.
+		let idx = (point.floor() as usize).min(line.len() - 1);
+		let next = (idx + 1).min(line.len() - 1);
+		let pressure = line[idx] + (line[next] - line[idx]) * point.fract();
+		// Even the lightest touch should leave a mark.
+		(thickness * pressure).max(factor as f32)
+	};
+	let (pad_x, pad_y, inner_width, inner_height) = padded_area(raster_size, options.padding * factor);
+	let normalized = normalize_strokes(lines, inner_width / inner_height, options);
+
+	// Draw the pixels.
+	// Walk each segment of the normalized lines and set pixels between start and stops.
+	let mut coverage = CoverageMap::new(raster_size.0, raster_size.1);
+	for (line_idx, norm_line) in normalized.iter().enumerate() {
+		if norm_line.len() == 1 { // A tap.  There are no segments to walk, so stamp the point on its own.
+			let pt = norm_line[0];
+			stamp_disk(&mut coverage, pad_x + pt.x*inner_width, pad_y + pt.y*inner_height, thickness_at(line_idx, 0.0), options.antialias);
+			continue;
+		}
+		for (idx, (pt_a, pt_b)) in norm_line.iter().zip(norm_line.iter().skip(1)).enumerate() {
+			let dx = pt_b.x - pt_a.x;
+			let dy = pt_b.y - pt_a.y;
+			// Count steps in output pixels, not canvas units.  How big the stroke was drawn says nothing about how many pixels it crosses.
+			let pixel_length = (dx * inner_width).abs().max((dy * inner_height).abs());
+			let pixel_steps = ((pixel_length * options.stamps_per_pixel.max(1) as f32).ceil() as u32).max(1);
+
+			for step in 0..pixel_steps {
+				let x = pt_a.x + (dx*step as f32 / pixel_steps as f32);
+				let y = pt_a.y + (dy*step as f32 / pixel_steps as f32);
+				// Convert the X/Y into the smaller form factor and set the pixel.
+				let thickness = thickness_at(line_idx, idx as f32 + step as f32 / pixel_steps as f32);
+				stamp_disk(&mut coverage, pad_x + x*inner_width, pad_y + y*inner_height, thickness, options.antialias);
+			}
+		}
+		if options.fill_closed && norm_line.len() >= 3 {
+			let outline: Vec<(f32, f32)> = norm_line.iter().map(|pt| (pad_x + pt.x*inner_width, pad_y + pt.y*inner_height)).collect();
+			let (first, last) = (outline[0], outline[outline.len() - 1]);
+			let gap = ((first.0 - last.0).powi(2) + (first.1 - last.1).powi(2)).sqrt();
+			if gap <= options.close_distance * factor as f32 {
+				fill_polygon(&mut coverage, &outline);
+			}
+		}
+		// Segments stop short of their end point, so corners can open up and the stroke ends a step early.
+		// Stamping every vertex closes the joins and gives the ends round caps.
+		if options.rounded_joins {
+			for (idx, pt) in norm_line.iter().enumerate() {
+				stamp_disk(&mut coverage, pad_x + pt.x*inner_width, pad_y + pt.y*inner_height, thickness_at(line_idx, idx as f32), options.antialias);
+			}
+		}
+	}
+
+	let coverage = if factor > 1 { box_downsample(&coverage, factor) } else { coverage };
+	let raster_size = output_size;
+
+	// Coverage becomes gray intensity.  Without anti-aliasing or supersampling this is only ever full black or full white.
+	// Grayscale output stores the same values in a single channel, a third of the size.
+	let level = |x, y| (coverage.get_pixel(x, y).0[0].powf(options.gamma) * 255.0).round() as u8;
+	let intensity = |x, y| {
+		let value = level(x, y);
+		if options.invert_colors { 255 - value } else { value }
+	};
+	if options.output_rgba {
+		let color = if options.invert_colors { 0 } else { 255 };
+		image::DynamicImage::ImageRgba8(image::RgbaImage::from_fn(raster_size.0, raster_size.1, |x, y| {
+			Rgba::from([color, color, color, level(x, y)])
+		}))
+	} else if options.output_grayscale {
+		image::DynamicImage::ImageLuma8(image::GrayImage::from_fn(raster_size.0, raster_size.1, |x, y| Luma::from([intensity(x, y)])))
+	} else {
+		image::DynamicImage::ImageRgb8(image::RgbImage::from_fn(raster_size.0, raster_size.1, |x, y| {
+			let value = intensity(x, y);
+			Rgb::from([value, value, value])
+		}))
+	}
+}
+
+/// Mix `options.noise_level` of Gaussian or salt-and-pepper noise into a rasterized image, like a noisy sensor would.
+/// Transparent output gets its noise in the alpha channel, everything else in one gray offset shared by the color channels.
+pub fn add_noise(img: &mut image::DynamicImage, options: &RasterOptions, rng: &mut impl Rng) {
+	if options.noise_level <= 0.0 { return; }
+	let mut noisy = |value: u8| -> u8 {
+		if options.salt_and_pepper {
+			if rng.gen::<f32>() < options.noise_level / 2.0 {
+				if rng.gen() { 255 } else { 0 }
+			} else {
+				value
+			}
+		} else {
+			// Box-Muller, so we don't need a distributions crate for one normal sample.
+			let (u1, u2): (f32, f32) = (1.0 - rng.gen::<f32>(), rng.gen());
+			let normal = (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos();
+			(value as f32 + normal * options.noise_level * 128.0).round().clamp(0.0, 255.0) as u8
+		}
+	};
+	match img {
+		image::DynamicImage::ImageLuma8(gray) => gray.pixels_mut().for_each(|p| p.0[0] = noisy(p.0[0])),
+		image::DynamicImage::ImageRgba8(rgba) => rgba.pixels_mut().for_each(|p| p.0[3] = noisy(p.0[3])),
+		image::DynamicImage::ImageRgb8(rgb) => rgb.pixels_mut().for_each(|p| {
+			let value = noisy(p.0[0]);
+			p.0 = [value; 3];
+		}),
+		_ => {}
+	}
+}
+
+/// Lay a rasterized drawing over a background picture, keeping its stroke color.  The background is stretched to the
+/// raster, or repeated at its own size with `options.tile_background`.  The result is opaque, so transparent output loses its alpha.
+pub fn composite_background(img: &image::DynamicImage, background: &image::RgbImage, options: &RasterOptions) -> image::DynamicImage {
+	let (width, height) = (img.width(), img.height());
+	if background.width() == 0 || background.height() == 0 { return img.clone(); }
+	let fitted = if options.tile_background {
+		image::RgbImage::from_fn(width, height, |x, y| *background.get_pixel(x % background.width(), y % background.height()))
+	} else {
+		image::imageops::resize(background, width, height, image::imageops::FilterType::Triangle)
+	};
+	// How much ink each pixel has, from 0 to 1.  Black on white output stores ink as darkness.
+	let coverage = |x, y| -> f32 {
+		if options.output_rgba {
+			img.get_pixel(x, y).0[3] as f32 / 255.0
+		} else {
+			let value = img.get_pixel(x, y).0[0] as f32 / 255.0;
+			if options.invert_colors { 1.0 - value } else { value }
+		}
+	};
+	let ink = if options.invert_colors { 0.0 } else { 255.0 };
+	let blended = image::RgbImage::from_fn(width, height, |x, y| {
+		let c = coverage(x, y);
+		Rgb(fitted.get_pixel(x, y).0.map(|bg| (bg as f32 * (1.0 - c) + ink * c).round() as u8))
+	});
+	if options.output_grayscale && !options.output_rgba {
+		image::DynamicImage::ImageLuma8(image::DynamicImage::ImageRgb8(blended).to_luma8())
+	} else {
+		image::DynamicImage::ImageRgb8(blended)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn normalize_fills_unit_square() {
+		let lines = vec![vec![egui::pos2(10.0, 20.0), egui::pos2(30.0, 60.0)]];
+		let normalized = normalize_strokes(&lines, 1.0, &RasterOptions::default());
+		assert!(normalized[0][0].distance(egui::pos2(0.0, 0.0)) < 1e-4);
+		assert!(normalized[0][1].distance(egui::pos2(1.0, 1.0)) < 1e-4);
+	}
+
+	#[test]
+	fn normalize_preserving_aspect_centers_the_short_axis() {
+		let lines = vec![vec![egui::pos2(0.0, 0.0), egui::pos2(10.0, 20.0)]];
+		let options = RasterOptions { preserve_aspect: true, ..Default::default() };
+		let normalized = normalize_strokes(&lines, 1.0, &options);
+		assert!((normalized[0][0].x - 0.25).abs() < 1e-4 && (normalized[0][1].x - 0.75).abs() < 1e-4);
+		assert!(normalized[0][0].y.abs() < 1e-4 && (normalized[0][1].y - 1.0).abs() < 1e-4);
+	}
+
+	#[test]
+	fn simplify_drops_collinear_points_and_keeps_corners() {
+		let line = [egui::pos2(0.0, 0.0), egui::pos2(5.0, 0.0), egui::pos2(10.0, 0.0), egui::pos2(10.0, 10.0)];
+		assert_eq!(simplify(&line, 0.01), vec![line[0], line[2], line[3]]);
+		assert_eq!(simplify(&line, 0.0).len(), line.len());
+	}
+
+	#[test]
+	fn rasterize_matches_the_requested_size() {
+		let lines = vec![vec![egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)]];
+		let img = rasterize(&lines, &[vec![1.0, 1.0]], (28, 14), &RasterOptions::default());
+		assert_eq!(img.dimensions(), (28, 14));
+	}
+}
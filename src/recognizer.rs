@@ -0,0 +1,178 @@
+//! A $1 unistroke recognizer (Wobbrock, Wilson and Li, 2007), to check while collecting that the classes can be told apart.
+//! Drawings with several strokes are joined end to end in the order they were drawn and treated as one stroke.
+
+use eframe::egui;
+
+/// Points every drawing is resampled to before comparing.
+const RESAMPLE_POINTS: usize = 64;
+/// Side of the square drawings are scaled into.  Scores are relative to it, so its value doesn't matter otherwise.
+const SQUARE_SIZE: f32 = 250.0;
+/// How far either way a drawing is rotated to find its best match, and when the search stops narrowing.
+const ANGLE_RANGE: f32 = std::f32::consts::FRAC_PI_4;
+const ANGLE_PRECISION: f32 = 2.0 * std::f32::consts::PI / 180.0;
+
+/// Walk the polyline by arc length and emit `n` evenly spaced points, starting and ending on its endpoints.
+/// This is the resampling step from the $1/$N family of recognizers.
+pub fn resample_stroke(line: &[egui::Pos2], n: usize) -> Vec<egui::Pos2> {
+	if line.is_empty() || n == 0 {
+		return vec![];
+	}
+	let length: f32 = line.windows(2).map(|w| w[0].distance(w[1])).sum();
+	if n == 1 || length <= 0.0 {
+		return vec![line[0]; n];
+	}
+
+	let interval = length / (n - 1) as f32;
+	let mut result = Vec::with_capacity(n);
+	result.push(line[0]);
+	let mut accumulated = 0.0;
+	let mut prev = line[0];
+	let mut idx = 1;
+	while idx < line.len() && result.len() < n {
+		let d = prev.distance(line[idx]);
+		if d > 0.0 && accumulated + d >= interval {
+			// The next sample falls somewhere on this segment.  Emit it and keep walking from there.
+			let q = prev + (line[idx] - prev) * ((interval - accumulated) / d);
+			result.push(q);
+			prev = q;
+			accumulated = 0.0;
+		} else {
+			accumulated += d;
+			prev = line[idx];
+			idx += 1;
+		}
+	}
+	// Rounding can leave us a point short of the end.
+	while result.len() < n {
+		result.push(*line.last().unwrap());
+	}
+	result
+}
+
+/// One known drawing of a class, already normalized for comparison.
+#[derive(Clone, PartialEq)]
+pub struct Template {
+	pub class_name: String,
+	points: Vec<egui::Pos2>,
+}
+
+impl Template {
+	/// None if the strokes have no length to compare, e.g. a single tap.
+	pub fn new(class_name: &str, strokes: &[Vec<egui::Pos2>]) -> Option<Template> {
+		normalize(strokes).map(|points| Template { class_name: class_name.to_owned(), points })
+	}
+}
+
+/// The class of the template closest to `strokes`, with a score from 0 (nothing alike) to 1 (identical).
+pub fn recognize<'a>(strokes: &[Vec<egui::Pos2>], templates: &'a [Template]) -> Option<(&'a str, f32)> {
+	let points = normalize(strokes)?;
+	let (best, distance) = templates.iter()
+		.map(|template| (template, distance_at_best_angle(&points, &template.points)))
+		.min_by(|a, b| a.1.total_cmp(&b.1))?;
+	let half_diagonal = 0.5 * (2.0 * SQUARE_SIZE * SQUARE_SIZE).sqrt();
+	Some((best.class_name.as_str(), (1.0 - distance / half_diagonal).max(0.0)))
+}
+
+/// Resample, rotate so the first point is at angle zero from the centroid, scale into the square and center on the origin.
+fn normalize(strokes: &[Vec<egui::Pos2>]) -> Option<Vec<egui::Pos2>> {
+	let path: Vec<egui::Pos2> = strokes.iter().flatten().copied().collect();
+	let points = resample_stroke(&path, RESAMPLE_POINTS);
+	let c = centroid(&points)?;
+	let indicative = (points[0].y - c.y).atan2(points[0].x - c.x);
+	let points = rotate(&points, -indicative);
+
+	let (mut min, mut max) = (points[0], points[0]);
+	for p in &points {
+		min = min.min(*p);
+		max = max.max(*p);
+	}
+	let size = max - min;
+	if size.x.max(size.y) <= 0.0 {
+		return None;
+	}
+	// A straight line has no extent on one axis.  Stretching that to the square would make noise of it, so it keeps its scale.
+	let scale = |extent: f32| if extent > 0.0 { SQUARE_SIZE / extent } else { 1.0 };
+	let points: Vec<egui::Pos2> = points.iter().map(|p| egui::pos2(p.x * scale(size.x), p.y * scale(size.y))).collect();
+	let c = centroid(&points)?;
+	Some(points.iter().map(|p| *p - c.to_vec2()).collect())
+}
+
+fn centroid(points: &[egui::Pos2]) -> Option<egui::Pos2> {
+	if points.is_empty() {
+		return None;
+	}
+	let sum = points.iter().fold(egui::Vec2::ZERO, |sum, p| sum + p.to_vec2());
+	Some((sum / points.len() as f32).to_pos2())
+}
+
+/// Rotate around the centroid.
+fn rotate(points: &[egui::Pos2], angle: f32) -> Vec<egui::Pos2> {
+	let c = centroid(points).unwrap_or(egui::Pos2::ZERO);
+	let (sin, cos) = angle.sin_cos();
+	points.iter().map(|p| {
+		let d = *p - c;
+		egui::pos2(c.x + d.x * cos - d.y * sin, c.y + d.x * sin + d.y * cos)
+	}).collect()
+}
+
+/// Mean distance between corresponding points.
+fn path_distance(a: &[egui::Pos2], b: &[egui::Pos2]) -> f32 {
+	a.iter().zip(b.iter()).map(|(p, q)| p.distance(*q)).sum::<f32>() / a.len().max(1) as f32
+}
+
+/// The smallest path distance over rotations of `points` within the angle range, found by golden section search.
+fn distance_at_best_angle(points: &[egui::Pos2], template: &[egui::Pos2]) -> f32 {
+	let phi = 0.5 * (5f32.sqrt() - 1.0);
+	let (mut a, mut b) = (-ANGLE_RANGE, ANGLE_RANGE);
+	let mut x1 = phi * a + (1.0 - phi) * b;
+	let mut f1 = path_distance(&rotate(points, x1), template);
+	let mut x2 = (1.0 - phi) * a + phi * b;
+	let mut f2 = path_distance(&rotate(points, x2), template);
+	while (b - a).abs() > ANGLE_PRECISION {
+		if f1 < f2 {
+			b = x2;
+			x2 = x1;
+			f2 = f1;
+			x1 = phi * a + (1.0 - phi) * b;
+			f1 = path_distance(&rotate(points, x1), template);
+		} else {
+			a = x1;
+			x1 = x2;
+			f1 = f2;
+			x2 = (1.0 - phi) * a + phi * b;
+			f2 = path_distance(&rotate(points, x2), template);
+		}
+	}
+	f1.min(f2)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn resample_keeps_count_and_endpoints() {
+		let line = [egui::pos2(0.0, 0.0), egui::pos2(10.0, 0.0), egui::pos2(10.0, 10.0)];
+		for n in [2, 3, 16, 64] {
+			let resampled = resample_stroke(&line, n);
+			assert_eq!(resampled.len(), n);
+			assert_eq!(resampled[0], line[0]);
+			assert!(resampled[n - 1].distance(line[2]) < 1e-3);
+		}
+	}
+
+	#[test]
+	fn resample_spaces_points_evenly() {
+		let resampled = resample_stroke(&[egui::pos2(0.0, 0.0), egui::pos2(9.0, 0.0)], 10);
+		for (i, p) in resampled.iter().enumerate() {
+			assert!((p.x - i as f32).abs() < 1e-3);
+		}
+	}
+
+	#[test]
+	fn resample_degenerate_lines() {
+		assert!(resample_stroke(&[], 8).is_empty());
+		assert!(resample_stroke(&[egui::pos2(1.0, 2.0)], 0).is_empty());
+		assert_eq!(resample_stroke(&[egui::pos2(1.0, 2.0); 3], 4), vec![egui::pos2(1.0, 2.0); 4]);
+	}
+}